@@ -1,64 +1,653 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::Fields;
 
-#[proc_macro_derive(ToSqlString)]
+#[proc_macro_derive(ToSqlString, attributes(filter))]
 pub fn sql_string_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
     impl_tosqlstr_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn impl_tosqlstr_macro(ast: &syn::DeriveInput) -> TokenStream {
+#[derive(Default)]
+struct FilterAttributes {
+    op: Option<String>,
+    column: Option<String>,
+    sortable: bool,
+}
+
+impl FilterAttributes {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut attributes = Self::default();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("filter")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("op") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    let op = value.value();
+                    if !matches!(
+                        op.as_str(),
+                        "eq" | "ilike"
+                            | "prefix"
+                            | "gte"
+                            | "lte"
+                            | "in"
+                            | "is_null"
+                    ) {
+                        return Err(syn::Error::new_spanned(
+                            value,
+                            "unsupported filter operator",
+                        ));
+                    }
+                    attributes.op = Some(op);
+                } else if meta.path.is_ident("column") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    attributes.column = Some(value.value());
+                } else if meta.path.is_ident("sortable") {
+                    attributes.sortable = true;
+                } else {
+                    return Err(meta.error("unsupported filter attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attributes)
+    }
+}
+
+fn is_vec(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Vec"),
+        _ => false,
+    }
+}
+
+// `FilterKind` variant of a field type, element type for lists
+fn filter_kind(ty: &syn::Type) -> Option<proc_macro2::Ident> {
+    if is_text(ty) {
+        return Some(format_ident!("Text"));
+    }
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    let kind = match segment.ident.to_string().as_str() {
+        "Vec" => {
+            let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+            else {
+                return None;
+            };
+            let Some(syn::GenericArgument::Type(inner)) = args.args.first()
+            else {
+                return None;
+            };
+            return filter_kind(inner);
+        }
+        "bool" => "Bool",
+        "i16" => "SmallInt",
+        "i32" => "Int",
+        "i64" => "BigInt",
+        "f64" => "Double",
+        "DateTime" => "Timestamp",
+        _ => return None,
+    };
+    Some(format_ident!("{}", kind))
+}
+
+fn impl_tosqlstr_macro(
+    ast: &syn::DeriveInput,
+) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    let gen = match &ast.data {
-        syn::Data::Struct(ref data) => {
-            let fields = match &data.fields {
-                Fields::Named(field) => &field.named,
-                _ => panic!("Only named fields are supported"),
+    let fields = named_fields(ast, "ToSqlString")?;
+
+    // columns usable in filter expressions, keyed by the column name
+    // without its table prefix
+    let mut filter_columns = Vec::new();
+    let mut filter_names = Vec::new();
+    // sortable columns should be NOT NULL, keyset cursors compare them
+    let mut sort_columns = Vec::new();
+    for field in fields {
+        let attributes = FilterAttributes::parse(field)?;
+        if attributes.op.as_deref() == Some("is_null") {
+            continue;
+        }
+        let column = attributes.column.unwrap_or_else(|| {
+            let ident = field.ident.as_ref().unwrap().to_string();
+            ident.trim_start_matches("r#").to_string()
+        });
+        let name = column.rsplit('.').next().unwrap().to_string();
+        let Some(kind) = filter_kind(option_inner(&field.ty)) else {
+            if attributes.sortable {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "unsupported type for a sortable field",
+                ));
+            }
+            continue;
+        };
+        let filter_column = quote! {
+            Self::Column {
+                name: #name,
+                column: #column,
+                kind: Self::ColumnKind::#kind,
+            }
+        };
+        if attributes.sortable {
+            sort_columns.push(filter_column.clone());
+        }
+        if filter_names.contains(&name) {
+            continue;
+        }
+        filter_columns.push(filter_column);
+        filter_names.push(name);
+    }
+
+    // without an `op`, text fields use the caller's operator with a
+    // `%value%` pattern, lists `= ANY` and other types `=`
+    let field_operations = fields
+        .iter()
+        .map(|field| {
+            let attributes = FilterAttributes::parse(field)?;
+            let field_name = field.ident.as_ref().unwrap();
+            let column = attributes.column.unwrap_or_else(|| {
+                field_name.to_string().trim_start_matches("r#").to_string()
+            });
+            let inner = option_inner(&field.ty);
+            let op = attributes.op.unwrap_or_else(|| {
+                if is_vec(inner) {
+                    "in".to_string()
+                } else if is_text(inner) {
+                    "default".to_string()
+                } else {
+                    "eq".to_string()
+                }
+            });
+
+            let (condition, param) = match op.as_str() {
+                "is_null" => {
+                    return Ok(quote! {
+                        if let Some(value) = self.#field_name.as_ref() {
+                            query_str += format!(
+                                "{} {} {} ",
+                                if query_str.len() == 0 {"WHERE"} else {separator},
+                                #column,
+                                if *value {"IS NULL"} else {"IS NOT NULL"},
+                            ).as_str();
+                        }
+                    });
+                }
+                "default" => (
+                    quote!(format!("{} {} ${}", #column, operator, counter)),
+                    quote!(format!(
+                        "%{}%",
+                        <Self as ToSqlString>::escape_like(value)
+                    )),
+                ),
+                "ilike" => (
+                    quote!(format!("{} ILIKE ${}", #column, counter)),
+                    quote!(format!(
+                        "%{}%",
+                        <Self as ToSqlString>::escape_like(value)
+                    )),
+                ),
+                "prefix" => (
+                    quote!(format!("{} ILIKE ${}", #column, counter)),
+                    quote!(format!(
+                        "{}%",
+                        <Self as ToSqlString>::escape_like(value)
+                    )),
+                ),
+                "in" => (
+                    quote!(format!("{} = ANY(${})", #column, counter)),
+                    quote!(value.clone()),
+                ),
+                "gte" => (
+                    quote!(format!("{} >= ${}", #column, counter)),
+                    quote!(value.clone()),
+                ),
+                "lte" => (
+                    quote!(format!("{} <= ${}", #column, counter)),
+                    quote!(value.clone()),
+                ),
+                _ => (
+                    quote!(format!("{} = ${}", #column, counter)),
+                    quote!(value.clone()),
+                ),
             };
 
-            let field_operations = fields.iter().map(|field| {
-                let field_name = &field.ident;
+            Ok(quote! {
+                if let Some(value) = self.#field_name.as_ref() {
+                    query_str += format!(
+                        "{} {} ",
+                        if query_str.len() == 0 {"WHERE"} else {separator},
+                        #condition,
+                    ).as_str();
+
+                    counter += 1;
+                    query_param.push(Box::new(#param));
+                }
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(quote! {
+        impl ToSqlString for #name {
+            const FILTER_COLUMNS: &'static [Self::Column] =
+                &[#(#filter_columns),*];
+            const SORT_COLUMNS: &'static [Self::Column] =
+                &[#(#sort_columns),*];
+
+            fn as_sql_string(
+                &self,
+                operator: &str,
+                separator: &str,
+            ) -> (
+                String,
+                Vec<Box<dyn ::tokio_postgres::types::ToSql + Sync + Send>>,
+            ) {
+                let mut counter = 1;
+                let mut query_str = String::new();
+                let mut query_param:
+                    Vec<Box<dyn ::tokio_postgres::types::ToSql + Sync + Send>> =
+                    Vec::new();
+                #(#field_operations)*
+
+                (query_str, query_param)
+            }
+        }
+    })
+}
 
+#[proc_macro_derive(FromRow, attributes(column))]
+pub fn from_row_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_from_row_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct ColumnAttributes {
+    rename: Option<String>,
+    default: bool,
+    flatten: bool,
+    try_from: Option<syn::Type>,
+}
+
+impl ColumnAttributes {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut attributes = Self::default();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("column")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    attributes.rename = Some(value.value());
+                } else if meta.path.is_ident("default") {
+                    attributes.default = true;
+                } else if meta.path.is_ident("flatten") {
+                    attributes.flatten = true;
+                } else if meta.path.is_ident("try_from") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    attributes.try_from = Some(value.parse()?);
+                } else {
+                    return Err(meta.error("unsupported column attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        if attributes.flatten
+            && (attributes.rename.is_some() || attributes.try_from.is_some())
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`flatten` can't be combined with `rename` or `try_from`",
+            ));
+        }
+        Ok(attributes)
+    }
+}
+
+fn impl_from_row_macro(
+    ast: &syn::DeriveInput,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let fields = named_fields(ast, "FromRow")?;
+
+    // the row lifetime is the struct's own lifetime so fields can borrow
+    // from the row, owned structs get a fresh one
+    let lifetimes: Vec<_> = ast.generics.lifetimes().collect();
+    if lifetimes.len() > 1
+        || ast.generics.type_params().next().is_some()
+        || ast.generics.const_params().next().is_some()
+    {
+        return Err(syn::Error::new_spanned(
+            &ast.generics,
+            "FromRow supports at most one lifetime and no type parameters",
+        ));
+    }
+    let (lifetime, impl_generics) = match lifetimes.first() {
+        Some(param) => {
+            let lifetime = &param.lifetime;
+            (quote!(#lifetime), quote!(<#lifetime>))
+        }
+        None => (quote!('__row), quote!(<'__row>)),
+    };
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    let field_values = fields
+        .iter()
+        .map(|field| {
+            let attributes = ColumnAttributes::parse(field)?;
+            let ident = field.ident.as_ref().unwrap();
+            let ty = &field.ty;
+            if attributes.flatten {
+                return Ok(quote! {
+                    #ident: <#ty as FromRow<#lifetime>>::from_row(row)?
+                });
+            }
+
+            let column = attributes.rename.unwrap_or_else(|| {
+                ident.to_string().trim_start_matches("r#").to_string()
+            });
+            let value = match &attributes.try_from {
+                Some(source) => quote! {{
+                    let value: #source = row.try_get(#column)?;
+                    <#ty as ::core::convert::TryFrom<#source>>::try_from(value)
+                        .map_err(|_| {
+                            <Self as FromRow<#lifetime>>::invalid_column(#column)
+                        })?
+                }},
+                None => quote!(row.try_get(#column)?),
+            };
+            Ok(if attributes.default {
                 quote! {
-                    if (self.#field_name.is_some()){
-                        query_str += format!(
-                        "{} {} {} ${} ",
-                        if query_str.len() == 0 {"WHERE"} else {separator},
-                        stringify!(#field_name),
-                        operator,
-                        counter,
-                        ).as_str();
-
-                        counter += 1;
-                        query_param.push(
-                            format!("%{}%", self.#field_name.as_ref().unwrap())
-                        );
+                    #ident: if row.columns().iter().any(|c| c.name() == #column) {
+                        #value
+                    } else {
+                        ::core::default::Default::default()
                     }
                 }
-            });
+            } else {
+                quote!(#ident: #value)
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
 
+    Ok(quote! {
+        impl #impl_generics FromRow<#lifetime> for #name #ty_generics {
+            fn from_row(
+                row: &#lifetime ::tokio_postgres::Row,
+            ) -> ::core::result::Result<Self, Self::Error> {
+                Ok(Self {
+                    #(#field_values),*
+                })
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(ToSqlUpdate, attributes(sql))]
+pub fn sql_update_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_sql_update_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct SqlAttributes {
+    rename: Option<String>,
+    skip: bool,
+    default: bool,
+}
+
+impl SqlAttributes {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut attributes = Self::default();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("sql")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    attributes.rename = Some(value.value());
+                } else if meta.path.is_ident("skip") {
+                    attributes.skip = true;
+                } else if meta.path.is_ident("default") {
+                    attributes.default = true;
+                } else {
+                    return Err(meta.error("unsupported sql attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attributes)
+    }
+
+    fn column(&self, ident: &syn::Ident) -> String {
+        self.rename.clone().unwrap_or_else(|| {
+            ident.to_string().trim_start_matches("r#").to_string()
+        })
+    }
+}
+
+fn named_fields<'a>(
+    ast: &'a syn::DeriveInput,
+    derive: &str,
+) -> syn::Result<&'a syn::punctuated::Punctuated<syn::Field, syn::Token![,]>> {
+    match &ast.data {
+        syn::Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(&fields.named),
+            _ => Err(syn::Error::new_spanned(
+                ast,
+                format!("{} only supports structs with named fields", derive),
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            ast,
+            format!("{} can only be used on structs", derive),
+        )),
+    }
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+// `T` of an `Option<T>`, the type itself otherwise
+fn option_inner(ty: &syn::Type) -> &syn::Type {
+    if let syn::Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "Option" {
+                if let syn::PathArguments::AngleBracketed(args) =
+                    &segment.arguments
+                {
+                    if let Some(syn::GenericArgument::Type(inner)) =
+                        args.args.first()
+                    {
+                        return inner;
+                    }
+                }
+            }
+        }
+    }
+    ty
+}
+
+fn is_text(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Reference(reference) => is_text(&reference.elem),
+        syn::Type::Path(path) => {
+            path.path.segments.last().is_some_and(|segment| {
+                matches!(
+                    segment.ident.to_string().as_str(),
+                    "String" | "str" | "Cow"
+                )
+            })
+        }
+        _ => false,
+    }
+}
+
+fn impl_sql_update_macro(
+    ast: &syn::DeriveInput,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        ast.generics.split_for_impl();
+
+    // `None` leaves the column untouched, `Some(None)` of an
+    // `Option<Option<T>>` sets it to NULL
+    let assignments = named_fields(ast, "ToSqlUpdate")?
+        .iter()
+        .map(|field| {
+            let attributes = SqlAttributes::parse(field)?;
+            if attributes.skip {
+                return Ok(quote!());
+            }
+            if !is_option(&field.ty) {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "ToSqlUpdate fields must be `Option<T>` or `Option<Option<T>>`",
+                ));
+            }
+            let ident = field.ident.as_ref().unwrap();
+            let column = attributes.column(ident);
+            Ok(quote! {
+                if let Some(value) = &self.#ident {
+                    if !query_str.is_empty() {
+                        query_str += ", ";
+                    }
+                    query_str += format!("{} = ${}", #column, counter).as_str();
+                    counter += 1;
+                    query_param.push(value);
+                }
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(quote! {
+        impl #impl_generics ToSqlUpdate for #name #ty_generics #where_clause {
+            fn as_sql_update(
+                &self,
+                start: usize,
+            ) -> ::core::result::Result<
+                (String, Vec<&(dyn ::tokio_postgres::types::ToSql + Sync)>),
+                Self::Error,
+            > {
+                let mut counter = start;
+                let mut query_str = String::new();
+                let mut query_param:
+                    Vec<&(dyn ::tokio_postgres::types::ToSql + Sync)> = Vec::new();
+                #(#assignments)*
+
+                if query_param.is_empty() {
+                    return Err(<Self as ToSqlUpdate>::nothing_to_update());
+                }
+                Ok((query_str, query_param))
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(ToSqlInsert, attributes(sql))]
+pub fn sql_insert_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_sql_insert_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn impl_sql_insert_macro(
+    ast: &syn::DeriveInput,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        ast.generics.split_for_impl();
+
+    let mut table = None;
+    for attr in ast.attrs.iter().filter(|a| a.path().is_ident("sql")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                table = Some(value.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported sql attribute"))
+            }
+        })?;
+    }
+    let table = table.ok_or_else(|| {
+        syn::Error::new_spanned(
+            name,
+            "ToSqlInsert requires `#[sql(table = \"...\")]`",
+        )
+    })?;
+
+    let mut columns = Vec::new();
+    let mut values = Vec::new();
+    for field in named_fields(ast, "ToSqlInsert")? {
+        let attributes = SqlAttributes::parse(field)?;
+        if attributes.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap();
+        let separator = if values.is_empty() {
+            quote!()
+        } else {
+            quote!(query.push_str(", ");)
+        };
+
+        // `None` of a `default` field lets the database fill the column
+        let value = if attributes.default {
+            if !is_option(&field.ty) {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "`default` fields must be `Option<T>`",
+                ));
+            }
             quote! {
-                impl ToSqlString for #name {
-                    fn as_sql_string(
-                        &self,
-                        operator: &str,
-                        separator: &str,
-                        order_by: &str,
-                    ) -> (String, Vec<String>) {
-                        let mut counter = 1;
-                        let mut query_str = String::new();
-                        let mut query_param: Vec<String> = Vec::new();
-                        #(#field_operations)*
-
-                        (query_str, query_param)
+                match &self.#ident {
+                    Some(value) => {
+                        params.push(value);
+                        query.push_str(format!("${}", params.len()).as_str());
                     }
+                    None => query.push_str("DEFAULT"),
                 }
             }
+        } else {
+            quote! {
+                params.push(&self.#ident);
+                query.push_str(format!("${}", params.len()).as_str());
+            }
+        };
+        columns.push(attributes.column(ident));
+        values.push(quote!(#separator #value));
+    }
+
+    Ok(quote! {
+        impl #impl_generics ToSqlInsert for #name #ty_generics #where_clause {
+            const TABLE: &'static str = #table;
+            const COLUMNS: &'static [&'static str] = &[#(#columns),*];
+
+            fn push_values<'__param>(
+                &'__param self,
+                query: &mut String,
+                params: &mut Vec<&'__param (dyn ::tokio_postgres::types::ToSql + Sync)>,
+            ) {
+                query.push('(');
+                #(#values)*
+                query.push(')');
+            }
         }
-        _ => quote! {
-            compile_error!("Validate can only be used on structs");
-        },
-    };
-    gen.into()
+    })
 }
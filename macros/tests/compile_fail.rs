@@ -0,0 +1,6 @@
+// Shapes the derives reject, each with the error pointing at the cause
+#[test]
+fn unsupported_shapes() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
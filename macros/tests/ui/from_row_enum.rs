@@ -0,0 +1,9 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+enum Status {
+    Active,
+    Inactive,
+}
+
+fn main() {}
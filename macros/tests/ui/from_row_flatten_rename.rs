@@ -0,0 +1,11 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+struct Profile {
+    #[column(flatten, rename = "user")]
+    user: User,
+}
+
+struct User;
+
+fn main() {}
@@ -0,0 +1,6 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+struct Pair(i32, String);
+
+fn main() {}
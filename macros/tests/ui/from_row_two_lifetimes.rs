@@ -0,0 +1,9 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+struct User<'a, 'b> {
+    email: &'a str,
+    username: &'b str,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+struct Wrapper<T> {
+    value: T,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use macros::FromRow;
+
+#[derive(FromRow)]
+struct User {
+    #[column(skip)]
+    email: String,
+}
+
+fn main() {}
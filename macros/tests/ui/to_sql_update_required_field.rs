@@ -0,0 +1,8 @@
+use macros::ToSqlUpdate;
+
+#[derive(ToSqlUpdate)]
+struct ProfileChange {
+    first_name: String,
+}
+
+fn main() {}
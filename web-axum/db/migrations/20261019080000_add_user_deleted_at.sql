@@ -0,0 +1,8 @@
+-- migrate:up
+alter table users add column deleted_at timestamp with time zone;
+create index users_deleted_at_idx on users (deleted_at)
+    where deleted_at is not null;
+
+-- migrate:down
+drop index users_deleted_at_idx;
+alter table users drop column deleted_at;
@@ -0,0 +1,5 @@
+-- migrate:up
+alter table users add column inactive_reason varchar(255);
+
+-- migrate:down
+alter table users drop column inactive_reason;
@@ -0,0 +1,11 @@
+-- migrate:up
+create table user_sessions (
+    id varchar(255) NOT NULL PRIMARY KEY,
+    user_id varchar(255) not null references users (id) on delete cascade,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP not null,
+    expire_at timestamp with time zone not null
+);
+create index user_sessions_user_id_idx on user_sessions (user_id);
+
+-- migrate:down
+drop table user_sessions;
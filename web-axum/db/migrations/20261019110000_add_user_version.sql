@@ -0,0 +1,5 @@
+-- migrate:up
+alter table users add column version integer DEFAULT 1 not null;
+
+-- migrate:down
+alter table users drop column version;
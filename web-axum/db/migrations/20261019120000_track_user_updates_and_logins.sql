@@ -0,0 +1,22 @@
+-- migrate:up
+alter table users add column login_count integer DEFAULT 0 not null;
+alter table users add column last_login_ip inet;
+
+-- login bookkeeping alone is not a profile update
+create function users_set_update_at() returns trigger as $$
+begin
+    if new.last_login is not distinct from old.last_login then
+        new.update_at = now();
+    end if;
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger users_set_update_at before update on users
+    for each row execute function users_set_update_at();
+
+-- migrate:down
+drop trigger users_set_update_at on users;
+drop function users_set_update_at();
+alter table users drop column last_login_ip;
+alter table users drop column login_count;
@@ -0,0 +1,18 @@
+-- migrate:up
+alter table users drop constraint users_username_key;
+create unique index users_username_key on users (lower(username));
+alter table users add column username_changed_at timestamp with time zone;
+
+-- previous usernames keep resolving to their user for a grace period
+create table username_aliases (
+    username varchar(255) NOT NULL PRIMARY KEY,
+    user_id varchar(255) not null references users (id) on delete cascade,
+    expire_at timestamp with time zone not null
+);
+create index username_aliases_user_id_idx on username_aliases (user_id);
+
+-- migrate:down
+drop table username_aliases;
+alter table users drop column username_changed_at;
+drop index users_username_key;
+alter table users add constraint users_username_key unique (username);
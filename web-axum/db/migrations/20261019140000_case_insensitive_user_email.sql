@@ -0,0 +1,7 @@
+-- migrate:up
+alter table users drop constraint users_email_key;
+create unique index users_email_key on users (lower(email));
+
+-- migrate:down
+drop index users_email_key;
+alter table users add constraint users_email_key unique (email);
@@ -0,0 +1,13 @@
+-- migrate:up
+create table audit_events (
+    id VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_id varchar(255),
+    actor_id varchar(255),
+    action varchar(255) not null,
+    data jsonb DEFAULT '{}' not null,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP not null
+);
+create index audit_events_user_id_idx on audit_events (user_id);
+
+-- migrate:down
+drop table audit_events;
@@ -0,0 +1,10 @@
+-- migrate:up
+create table user_settings (
+    user_id varchar(255) NOT NULL PRIMARY KEY
+        references users (id) on delete cascade,
+    settings jsonb DEFAULT '{}' not null,
+    update_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP not null
+);
+
+-- migrate:down
+drop table user_settings;
@@ -0,0 +1,19 @@
+-- migrate:up
+alter table users add column erasure_requested_at timestamp with time zone;
+alter table users add column erased_at timestamp with time zone;
+
+-- personal data archives, the blob is removed once expired
+create table user_data_exports (
+    id VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_id varchar(255) not null references users (id) on delete cascade,
+    blob_key varchar(255) not null,
+    url varchar(255) not null,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP not null,
+    expire_at timestamp with time zone not null
+);
+create index user_data_exports_user_id_idx on user_data_exports (user_id);
+
+-- migrate:down
+drop table user_data_exports;
+alter table users drop column erased_at;
+alter table users drop column erasure_requested_at;
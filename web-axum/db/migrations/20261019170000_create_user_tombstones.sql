@@ -0,0 +1,11 @@
+-- migrate:up
+-- ids of users merged into another account keep resolving to it
+create table user_tombstones (
+    user_id varchar(255) NOT NULL PRIMARY KEY,
+    merged_into varchar(255) not null references users (id) on delete cascade,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP not null
+);
+create index user_tombstones_merged_into_idx on user_tombstones (merged_into);
+
+-- migrate:down
+drop table user_tombstones;
@@ -0,0 +1,5 @@
+-- migrate:up
+alter table users add column is_admin boolean DEFAULT false NOT NULL;
+
+-- migrate:down
+alter table users drop column is_admin;
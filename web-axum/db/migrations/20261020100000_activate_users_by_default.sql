@@ -0,0 +1,10 @@
+-- migrate:up
+-- new accounts can sign in right away, deactivation is an admin action
+alter table users alter column is_active set default true;
+-- accounts left inactive by the old default were never deactivated, a
+-- deactivation always records a reason
+update users set is_active = true, active_at = coalesce(active_at, create_at)
+where is_active is not true and inactive_reason is null;
+
+-- migrate:down
+alter table users alter column is_active set default false;
@@ -23,10 +23,40 @@ CREATE EXTENSION IF NOT EXISTS "uuid-ossp" WITH SCHEMA public;
 COMMENT ON EXTENSION "uuid-ossp" IS 'generate universally unique identifiers (UUIDs)';
 
 
+--
+-- Name: users_set_update_at(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.users_set_update_at() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+begin
+    if new.last_login is not distinct from old.last_login then
+        new.update_at = now();
+    end if;
+    return new;
+end;
+$$;
+
+
 SET default_tablespace = '';
 
 SET default_table_access_method = heap;
 
+--
+-- Name: audit_events; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.audit_events (
+    id character varying(255) NOT NULL,
+    user_id character varying(255),
+    actor_id character varying(255),
+    action character varying(255) NOT NULL,
+    data jsonb DEFAULT '{}'::jsonb NOT NULL,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+
 --
 -- Name: schema_migrations; Type: TABLE; Schema: public; Owner: -
 --
@@ -36,6 +66,65 @@ CREATE TABLE public.schema_migrations (
 );
 
 
+--
+-- Name: user_data_exports; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_data_exports (
+    id character varying(255) NOT NULL,
+    user_id character varying(255) NOT NULL,
+    blob_key character varying(255) NOT NULL,
+    url character varying(255) NOT NULL,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expire_at timestamp with time zone NOT NULL
+);
+
+
+--
+-- Name: user_sessions; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_sessions (
+    id character varying(255) NOT NULL,
+    user_id character varying(255) NOT NULL,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expire_at timestamp with time zone NOT NULL
+);
+
+
+--
+-- Name: user_settings; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_settings (
+    user_id character varying(255) NOT NULL,
+    settings jsonb DEFAULT '{}'::jsonb NOT NULL,
+    update_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+
+--
+-- Name: user_tombstones; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_tombstones (
+    user_id character varying(255) NOT NULL,
+    merged_into character varying(255) NOT NULL,
+    create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+
+--
+-- Name: username_aliases; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.username_aliases (
+    username character varying(255) NOT NULL,
+    user_id character varying(255) NOT NULL,
+    expire_at timestamp with time zone NOT NULL
+);
+
+
 --
 -- Name: users; Type: TABLE; Schema: public; Owner: -
 --
@@ -48,14 +137,31 @@ CREATE TABLE public.users (
     first_name character varying(255),
     last_name character varying(255),
     password text NOT NULL,
-    is_active boolean DEFAULT false,
+    is_active boolean DEFAULT true,
     create_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
     active_at timestamp with time zone,
     update_at timestamp with time zone,
-    last_login timestamp with time zone
+    last_login timestamp with time zone,
+    deleted_at timestamp with time zone,
+    inactive_reason character varying(255),
+    version integer DEFAULT 1 NOT NULL,
+    login_count integer DEFAULT 0 NOT NULL,
+    last_login_ip inet,
+    username_changed_at timestamp with time zone,
+    erasure_requested_at timestamp with time zone,
+    erased_at timestamp with time zone,
+    is_admin boolean DEFAULT false NOT NULL
 );
 
 
+--
+-- Name: audit_events audit_events_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_events
+    ADD CONSTRAINT audit_events_pkey PRIMARY KEY (id);
+
+
 --
 -- Name: schema_migrations schema_migrations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -65,11 +171,43 @@ ALTER TABLE ONLY public.schema_migrations
 
 
 --
--- Name: users users_email_key; Type: CONSTRAINT; Schema: public; Owner: -
+-- Name: user_data_exports user_data_exports_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
 
-ALTER TABLE ONLY public.users
-    ADD CONSTRAINT users_email_key UNIQUE (email);
+ALTER TABLE ONLY public.user_data_exports
+    ADD CONSTRAINT user_data_exports_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: user_sessions user_sessions_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_sessions
+    ADD CONSTRAINT user_sessions_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: user_settings user_settings_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_settings
+    ADD CONSTRAINT user_settings_pkey PRIMARY KEY (user_id);
+
+
+--
+-- Name: user_tombstones user_tombstones_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_tombstones
+    ADD CONSTRAINT user_tombstones_pkey PRIMARY KEY (user_id);
+
+
+--
+-- Name: username_aliases username_aliases_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.username_aliases
+    ADD CONSTRAINT username_aliases_pkey PRIMARY KEY (username);
 
 
 --
@@ -81,11 +219,106 @@ ALTER TABLE ONLY public.users
 
 
 --
--- Name: users users_username_key; Type: CONSTRAINT; Schema: public; Owner: -
+-- Name: audit_events_user_id_idx; Type: INDEX; Schema: public; Owner: -
 --
 
-ALTER TABLE ONLY public.users
-    ADD CONSTRAINT users_username_key UNIQUE (username);
+CREATE INDEX audit_events_user_id_idx ON public.audit_events USING btree (user_id);
+
+
+--
+-- Name: user_data_exports_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_data_exports_user_id_idx ON public.user_data_exports USING btree (user_id);
+
+
+--
+-- Name: user_sessions_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_sessions_user_id_idx ON public.user_sessions USING btree (user_id);
+
+
+--
+-- Name: user_tombstones_merged_into_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_tombstones_merged_into_idx ON public.user_tombstones USING btree (merged_into);
+
+
+--
+-- Name: username_aliases_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX username_aliases_user_id_idx ON public.username_aliases USING btree (user_id);
+
+
+--
+-- Name: users_deleted_at_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX users_deleted_at_idx ON public.users USING btree (deleted_at) WHERE (deleted_at IS NOT NULL);
+
+
+--
+-- Name: users_email_key; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE UNIQUE INDEX users_email_key ON public.users USING btree (lower((email)::text));
+
+
+--
+-- Name: users_username_key; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE UNIQUE INDEX users_username_key ON public.users USING btree (lower((username)::text));
+
+
+--
+-- Name: users users_set_update_at; Type: TRIGGER; Schema: public; Owner: -
+--
+
+CREATE TRIGGER users_set_update_at BEFORE UPDATE ON public.users FOR EACH ROW EXECUTE FUNCTION public.users_set_update_at();
+
+
+--
+-- Name: username_aliases username_aliases_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.username_aliases
+    ADD CONSTRAINT username_aliases_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_settings user_settings_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_settings
+    ADD CONSTRAINT user_settings_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_tombstones user_tombstones_merged_into_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_tombstones
+    ADD CONSTRAINT user_tombstones_merged_into_fkey FOREIGN KEY (merged_into) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_data_exports user_data_exports_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_data_exports
+    ADD CONSTRAINT user_data_exports_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_sessions user_sessions_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_sessions
+    ADD CONSTRAINT user_sessions_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
 
 
 --
@@ -98,4 +331,17 @@ ALTER TABLE ONLY public.users
 --
 
 INSERT INTO public.schema_migrations (version) VALUES
-    ('20240401065823');
+    ('20240401065823'),
+    ('20261019080000'),
+    ('20261019090000'),
+    ('20261019100000'),
+    ('20261019110000'),
+    ('20261019120000'),
+    ('20261019130000'),
+    ('20261019140000'),
+    ('20261019150000'),
+    ('20261019150100'),
+    ('20261019160000'),
+    ('20261019170000'),
+    ('20261020090000'),
+    ('20261020100000');
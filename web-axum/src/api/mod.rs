@@ -0,0 +1,53 @@
+use axum::{
+    http::{HeaderName, HeaderValue},
+    middleware,
+    response::Response,
+    Router,
+};
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::db::extractors::ConnectionPool;
+use crate::users;
+
+pub fn routes() -> Router<ConnectionPool> {
+    // unversioned routes kept until clients move to `/api/v1`
+    let legacy = deprecated(
+        v1_routes(),
+        Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap(),
+        Utc.with_ymd_and_hms(2027, 4, 19, 0, 0, 0).unwrap(),
+    );
+
+    // newer versions are mounted side by side, e.g. `.nest("/v2", ...)`,
+    // wrapping the older one with `deprecated` once it is superseded
+    Router::new().nest("/v1", v1_routes()).merge(legacy)
+}
+
+pub fn v1_routes() -> Router<ConnectionPool> {
+    Router::new().nest("/users", users::routes::auth_routes())
+}
+
+// Mark every response of `router` with `Deprecation` and `Sunset` headers
+pub fn deprecated(
+    router: Router<ConnectionPool>,
+    deprecated_at: DateTime<Utc>,
+    sunset_at: DateTime<Utc>,
+) -> Router<ConnectionPool> {
+    let deprecation =
+        HeaderValue::from_str(&format!("@{}", deprecated_at.timestamp()))
+            .unwrap();
+    let sunset = HeaderValue::from_str(
+        &sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+    )
+    .unwrap();
+
+    router.layer(middleware::map_response(move |mut response: Response| {
+        let deprecation = deprecation.clone();
+        let sunset = sunset.clone();
+        async move {
+            let headers = response.headers_mut();
+            headers.insert(HeaderName::from_static("deprecation"), deprecation);
+            headers.insert(HeaderName::from_static("sunset"), sunset);
+            response
+        }
+    }))
+}
@@ -0,0 +1,23 @@
+use serde_json::Value;
+use tokio_postgres::GenericClient;
+
+use crate::common::error::Result;
+use crate::common::utils::uuid7_b62;
+
+// Append an event about `user_id` performed by `actor_id`, accepting a
+// connection or a transaction so it can commit with the change it records
+pub async fn record_event<C: GenericClient>(
+    con: &C,
+    user_id: Option<&str>,
+    actor_id: Option<&str>,
+    action: &str,
+    data: Value,
+) -> Result<()> {
+    con.execute(
+        "INSERT INTO audit_events (id, user_id, actor_id, action, data) \
+        VALUES ($1, $2, $3, $4, $5)",
+        &[&uuid7_b62(), &user_id, &actor_id, &action, &data],
+    )
+    .await?;
+    Ok(())
+}
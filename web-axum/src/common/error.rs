@@ -14,6 +14,11 @@ use validator::ValidationErrors;
 
 pub type Result<T> = core::result::Result<T, AppError>;
 
+// Marks responses caused by a serialization failure so the transaction
+// middleware can replay the request
+#[derive(Clone, Copy)]
+pub struct SerializationFailure;
+
 pub enum AppError {
     UnexpectedError,
     FatalError(String),
@@ -21,6 +26,9 @@ pub enum AppError {
     ValidationErrors(ValidationErrors),
     ErrorResponse(ErrorResponse),
     NotFound(String),
+    Forbidden(String),
+    Unauthorized(String),
+    PreconditionFailed,
 }
 
 impl IntoResponse for AppError {
@@ -76,6 +84,20 @@ impl IntoResponse for AppError {
                         )
                             .into_response()
                     }
+                    Some(err)
+                        if err.code()
+                            == &SqlState::T_R_SERIALIZATION_FAILURE =>
+                    {
+                        let mut response = (
+                            StatusCode::CONFLICT,
+                            ErrorResponse::create_error(
+                                "Concurrent update, please retry",
+                            ),
+                        )
+                            .into_response();
+                        response.extensions_mut().insert(SerializationFailure);
+                        response
+                    }
                     Some(err) => {
                         error!("Unexpected - DB related error {:?}", err);
                         (StatusCode::BAD_REQUEST, "Unexpected error")
@@ -100,6 +122,27 @@ impl IntoResponse for AppError {
                 },
             )
                 .into_response(),
+            AppError::Forbidden(message) => (
+                StatusCode::FORBIDDEN,
+                ErrorResponse {
+                    errors: None,
+                    error: Some(Cow::Owned(message)),
+                },
+            )
+                .into_response(),
+            AppError::PreconditionFailed => (
+                StatusCode::PRECONDITION_FAILED,
+                ErrorResponse::create_error("Resource has been modified"),
+            )
+                .into_response(),
+            AppError::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    errors: None,
+                    error: Some(Cow::Owned(message)),
+                },
+            )
+                .into_response(),
         };
     }
 }
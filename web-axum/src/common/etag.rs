@@ -0,0 +1,87 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{
+        header::{IF_MATCH, IF_NONE_MATCH},
+        request::Parts,
+        HeaderValue,
+    },
+};
+use std::convert::Infallible;
+
+pub fn version_etag(version: i32) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).unwrap()
+}
+
+fn tag_matches(header: &str, etag: &HeaderValue, weak: bool) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+    header.split(',').map(str::trim).any(|tag| {
+        tag == "*"
+            || tag == etag
+            || (weak && tag.strip_prefix("W/") == Some(etag))
+    })
+}
+
+// `If-Match` request header, absent header matches any version
+pub struct IfMatch(pub Option<String>);
+
+impl IfMatch {
+    pub fn matches(&self, etag: &HeaderValue) -> bool {
+        self.0
+            .as_deref()
+            .is_none_or(|header| tag_matches(header, etag, false))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .headers
+                .get(IF_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        ))
+    }
+}
+
+// `If-None-Match` request header, absent header matches nothing
+pub struct IfNoneMatch(pub Option<String>);
+
+impl IfNoneMatch {
+    pub fn matches(&self, etag: &HeaderValue) -> bool {
+        self.0
+            .as_deref()
+            .is_some_and(|header| tag_matches(header, etag, true))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfNoneMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .headers
+                .get(IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        ))
+    }
+}
@@ -1,10 +1,12 @@
 use crate::common::response::ErrorResponse;
 use axum::async_trait;
-use axum::extract::rejection::{JsonRejection, QueryRejection};
-use axum::extract::{FromRequest, FromRequestParts, Json, Query, Request};
-use axum::http::request::Parts;
+use axum::body::Bytes;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, FromRequestParts, Json, Request};
+use axum::http::{header::CONTENT_TYPE, request::Parts, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 use std::fmt::Debug;
 use validator::{Validate, ValidationErrors};
 
@@ -22,8 +24,10 @@ impl<T: Debug + Validate> ValidateValue for Json<T> {
 #[derive(Debug)]
 pub enum ValidateRejection {
     JsonRejection(JsonRejection),
-    QueryRejection(QueryRejection),
+    InvalidQuery,
     ValidationErrors(ValidationErrors),
+    InvalidBody,
+    UnsupportedMediaType,
 }
 
 impl From<JsonRejection> for ValidateRejection {
@@ -38,23 +42,29 @@ impl From<ValidationErrors> for ValidateRejection {
     }
 }
 
-impl From<QueryRejection> for ValidateRejection {
-    fn from(value: QueryRejection) -> Self {
-        Self::QueryRejection(value)
-    }
-}
-
 impl IntoResponse for ValidateRejection {
     fn into_response(self) -> Response {
         println!("ValidateRejection: {:?}", self);
         let error_response = match self {
             ValidateRejection::JsonRejection(err) => ErrorResponse::from(err),
-            ValidateRejection::QueryRejection(err) => {
+            ValidateRejection::InvalidQuery => {
                 ErrorResponse::create_error("Invalid query params format")
             }
             ValidateRejection::ValidationErrors(err) => {
                 ErrorResponse::from(err)
             }
+            ValidateRejection::InvalidBody => {
+                ErrorResponse::create_error("Invalid json format")
+            }
+            ValidateRejection::UnsupportedMediaType => {
+                return (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    Json(ErrorResponse::create_error(
+                        "Unsupported content type",
+                    )),
+                )
+                    .into_response()
+            }
         };
         error_response.into_response()
     }
@@ -94,10 +104,47 @@ where
 
     async fn from_request_parts(
         parts: &mut Parts,
-        state: &S,
+        _state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let Query(query) = Query::<T>::from_request_parts(parts, state).await?;
+        // repeated keys (`?id=a&id=b`) deserialize into a `Vec`
+        let query: T =
+            serde_html_form::from_str(parts.uri.query().unwrap_or_default())
+                .map_err(|_| ValidateRejection::InvalidQuery)?;
         query.validate()?;
         Ok(Self(query))
     }
 }
+
+// `application/merge-patch+json` body, see `common::patch::merge_patch`
+#[derive(Debug)]
+pub struct MergePatch(pub Value);
+
+#[async_trait]
+impl<S> FromRequest<S> for MergePatch
+where
+    S: Send + Sync,
+{
+    type Rejection = ValidateRejection;
+
+    async fn from_request(
+        req: Request,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let is_merge_patch = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("application/merge-patch+json"))
+            .unwrap_or(false);
+        if !is_merge_patch {
+            return Err(ValidateRejection::UnsupportedMediaType);
+        }
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|_| ValidateRejection::InvalidBody)?;
+        let value = serde_json::from_slice(&body)
+            .map_err(|_| ValidateRejection::InvalidBody)?;
+        Ok(Self(value))
+    }
+}
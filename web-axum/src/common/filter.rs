@@ -0,0 +1,636 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::common::error::{AppError, Result};
+use crate::common::response::ErrorResponse;
+use crate::common::to_sql::{escape_like, SqlParam};
+
+// Limits keeping user supplied expressions cheap to compile and run
+static MAX_DEPTH: usize = 8;
+static MAX_CONDITIONS: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FilterKind {
+    Text,
+    Bool,
+    // only built by `#[derive(ToSqlString)]` for numeric fields, which no
+    // query struct has yet
+    #[allow(dead_code)]
+    SmallInt,
+    #[allow(dead_code)]
+    Int,
+    #[allow(dead_code)]
+    BigInt,
+    #[allow(dead_code)]
+    Double,
+    Timestamp,
+}
+
+// Column an expression may reference, generated by `#[derive(ToSqlString)]`
+#[derive(Debug)]
+pub struct FilterColumn {
+    pub name: &'static str,
+    pub column: &'static str,
+    pub kind: FilterKind,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ilike,
+    Prefix,
+    Gte,
+    Lte,
+    In,
+    IsNull,
+}
+
+impl FilterOp {
+    fn parse(op: &str) -> Option<Self> {
+        Some(match op {
+            "eq" => Self::Eq,
+            "ilike" => Self::Ilike,
+            "prefix" => Self::Prefix,
+            "gte" => Self::Gte,
+            "lte" => Self::Lte,
+            "in" => Self::In,
+            "is_null" => Self::IsNull,
+            _ => return None,
+        })
+    }
+}
+
+// Filter expression, as JSON:
+// `{"and": [{"or": [{"field": "first_name", "op": "ilike", "value": "x"},
+// ...]}, {"not": {"field": "is_active", "op": "eq", "value": false}}]}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FilterExpr {
+    And {
+        and: Vec<FilterExpr>,
+    },
+    Or {
+        or: Vec<FilterExpr>,
+    },
+    Not {
+        not: Box<FilterExpr>,
+    },
+    Condition {
+        field: String,
+        op: FilterOp,
+        value: Value,
+    },
+}
+
+fn filter_error(message: String) -> AppError {
+    AppError::from(ErrorResponse {
+        errors: Some(HashMap::from([(
+            "filter".to_string(),
+            Cow::Owned(message),
+        )])),
+        error: None,
+    })
+}
+
+impl FilterExpr {
+    // Query string form, e.g.
+    // `and(or(first_name.ilike.x,last_name.ilike.x),is_active.eq.true)`.
+    // Values may be double quoted to contain `,` or `)`, `in` values are
+    // separated by `|` and top level conditions are joined with AND.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+        };
+        let mut items = parser.parse_list(0)?;
+        if parser.pos != parser.chars.len() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Self::And { and: items }
+        })
+    }
+
+    // Parameterised SQL for the expression, columns are resolved through
+    // the `columns` whitelist and parameters appended to `params`
+    pub fn to_sql(
+        &self,
+        columns: &[FilterColumn],
+        params: &mut Vec<SqlParam>,
+    ) -> Result<String> {
+        let mut conditions = 0;
+        self.compile(columns, params, 0, &mut conditions)
+    }
+
+    // Append the expression to a query built by `as_sql_string`
+    pub fn push_where(
+        &self,
+        query: &mut String,
+        columns: &[FilterColumn],
+        params: &mut Vec<SqlParam>,
+    ) -> Result<()> {
+        let condition = self.to_sql(columns, params)?;
+        *query += if query.is_empty() { "WHERE " } else { "AND " };
+        *query += format!("{} ", condition).as_str();
+        Ok(())
+    }
+
+    fn compile(
+        &self,
+        columns: &[FilterColumn],
+        params: &mut Vec<SqlParam>,
+        depth: usize,
+        conditions: &mut usize,
+    ) -> Result<String> {
+        if depth > MAX_DEPTH {
+            return Err(filter_error("expression is nested too deeply".into()));
+        }
+        let group = |items: &[FilterExpr],
+                     separator: &str,
+                     params: &mut Vec<SqlParam>,
+                     conditions: &mut usize|
+         -> Result<String> {
+            if items.is_empty() {
+                return Err(filter_error("empty group".to_string()));
+            }
+            let parts = items
+                .iter()
+                .map(|item| {
+                    item.compile(columns, params, depth + 1, conditions)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(format!("({})", parts.join(separator)))
+        };
+
+        match self {
+            Self::And { and } => group(and, " AND ", params, conditions),
+            Self::Or { or } => group(or, " OR ", params, conditions),
+            Self::Not { not } => Ok(format!(
+                "NOT ({})",
+                not.compile(columns, params, depth + 1, conditions)?
+            )),
+            Self::Condition { field, op, value } => {
+                *conditions += 1;
+                if *conditions > MAX_CONDITIONS {
+                    return Err(filter_error(
+                        "too many conditions".to_string(),
+                    ));
+                }
+                let column = columns
+                    .iter()
+                    .find(|column| column.name == field)
+                    .ok_or_else(|| {
+                        filter_error(format!("unknown field `{}`", field))
+                    })?;
+                compile_condition(column, *op, value, params)
+            }
+        }
+    }
+}
+
+fn compile_condition(
+    column: &FilterColumn,
+    op: FilterOp,
+    value: &Value,
+    params: &mut Vec<SqlParam>,
+) -> Result<String> {
+    let name = column.column;
+    if op == FilterOp::IsNull {
+        return match to_bool(value) {
+            Some(true) => Ok(format!("{} IS NULL", name)),
+            Some(false) => Ok(format!("{} IS NOT NULL", name)),
+            None => Err(invalid_value(column)),
+        };
+    }
+
+    let condition = match op {
+        FilterOp::Ilike | FilterOp::Prefix => {
+            let (FilterKind::Text, Some(text)) = (column.kind, value.as_str())
+            else {
+                return Err(invalid_value(column));
+            };
+            let pattern = if op == FilterOp::Ilike {
+                format!("%{}%", escape_like(text))
+            } else {
+                format!("{}%", escape_like(text))
+            };
+            params.push(Box::new(pattern));
+            format!("{} ILIKE ${}", name, params.len())
+        }
+        FilterOp::In => {
+            let values: Vec<Value> = match value {
+                Value::Array(values) => values.clone(),
+                Value::String(values) => values
+                    .split('|')
+                    .map(|value| Value::String(value.to_string()))
+                    .collect(),
+                _ => return Err(invalid_value(column)),
+            };
+            params.push(to_list_param(column, &values)?);
+            format!("{} = ANY(${})", name, params.len())
+        }
+        _ => {
+            params.push(to_param(column, value)?);
+            let operator = match op {
+                FilterOp::Gte => ">=",
+                FilterOp::Lte => "<=",
+                _ => "=",
+            };
+            format!("{} {} ${}", name, operator, params.len())
+        }
+    };
+    Ok(condition)
+}
+
+fn invalid_value(column: &FilterColumn) -> AppError {
+    filter_error(format!("invalid value for `{}`", column.name))
+}
+
+fn to_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(value) => value.as_i64(),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(value) => value.as_f64(),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    value
+        .as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|value| value.with_timezone(&Utc))
+}
+
+// Convert the value to the Rust type matching the column, postgres
+// rejects parameters whose type differs from the column's
+pub fn to_param(column: &FilterColumn, value: &Value) -> Result<SqlParam> {
+    let param: Option<SqlParam> = match column.kind {
+        FilterKind::Text => value
+            .as_str()
+            .map(|value| Box::new(value.to_string()) as SqlParam),
+        FilterKind::Bool => {
+            to_bool(value).map(|value| Box::new(value) as SqlParam)
+        }
+        FilterKind::SmallInt => to_i64(value)
+            .and_then(|value| i16::try_from(value).ok())
+            .map(|value| Box::new(value) as SqlParam),
+        FilterKind::Int => to_i64(value)
+            .and_then(|value| i32::try_from(value).ok())
+            .map(|value| Box::new(value) as SqlParam),
+        FilterKind::BigInt => {
+            to_i64(value).map(|value| Box::new(value) as SqlParam)
+        }
+        FilterKind::Double => {
+            to_f64(value).map(|value| Box::new(value) as SqlParam)
+        }
+        FilterKind::Timestamp => {
+            to_timestamp(value).map(|value| Box::new(value) as SqlParam)
+        }
+    };
+    param.ok_or_else(|| invalid_value(column))
+}
+
+fn to_list_param(column: &FilterColumn, values: &[Value]) -> Result<SqlParam> {
+    fn collect<T>(
+        values: &[Value],
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        values.iter().map(convert).collect()
+    }
+
+    let param: Option<SqlParam> =
+        match column.kind {
+            FilterKind::Text => {
+                collect(values, |value| value.as_str().map(str::to_string))
+                    .map(|values| Box::new(values) as SqlParam)
+            }
+            FilterKind::Bool => collect(values, to_bool)
+                .map(|values| Box::new(values) as SqlParam),
+            FilterKind::SmallInt => collect(values, |value| {
+                to_i64(value).and_then(|value| i16::try_from(value).ok())
+            })
+            .map(|values| Box::new(values) as SqlParam),
+            FilterKind::Int => collect(values, |value| {
+                to_i64(value).and_then(|value| i32::try_from(value).ok())
+            })
+            .map(|values| Box::new(values) as SqlParam),
+            FilterKind::BigInt => collect(values, to_i64)
+                .map(|values| Box::new(values) as SqlParam),
+            FilterKind::Double => collect(values, to_f64)
+                .map(|values| Box::new(values) as SqlParam),
+            FilterKind::Timestamp => collect(values, to_timestamp)
+                .map(|values| Box::new(values) as SqlParam),
+        };
+    param.ok_or_else(|| invalid_value(column))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> AppError {
+        filter_error(format!("{} at position {}", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected `{}`", expected)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn parse_list(&mut self, depth: usize) -> Result<Vec<FilterExpr>> {
+        let mut items = vec![self.parse_expr(depth)?];
+        while self.peek() == Some(',') {
+            self.pos += 1;
+            items.push(self.parse_expr(depth)?);
+        }
+        Ok(items)
+    }
+
+    fn parse_expr(&mut self, depth: usize) -> Result<FilterExpr> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("expression is nested too deeply"));
+        }
+        let name = self.identifier();
+        if name.is_empty() {
+            return Err(self.error("expected a field or group"));
+        }
+
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            let expr = match name.as_str() {
+                "and" => FilterExpr::And {
+                    and: self.parse_list(depth + 1)?,
+                },
+                "or" => FilterExpr::Or {
+                    or: self.parse_list(depth + 1)?,
+                },
+                "not" => FilterExpr::Not {
+                    not: Box::new(self.parse_expr(depth + 1)?),
+                },
+                _ => return Err(self.error("unknown group")),
+            };
+            self.expect(')')?;
+            return Ok(expr);
+        }
+
+        self.expect('.')?;
+        let op = self.identifier();
+        let op = FilterOp::parse(&op)
+            .ok_or_else(|| self.error("unknown operator"))?;
+        self.expect('.')?;
+        let value = self.parse_value()?;
+        Ok(FilterExpr::Condition {
+            field: name,
+            op,
+            value: Value::String(value),
+        })
+    }
+
+    fn parse_value(&mut self) -> Result<String> {
+        let mut value = String::new();
+        if self.peek() == Some('"') {
+            self.pos += 1;
+            loop {
+                match self.peek() {
+                    Some('"') => {
+                        self.pos += 1;
+                        return Ok(value);
+                    }
+                    Some('\\') => {
+                        self.pos += 1;
+                        let Some(c) = self.peek() else {
+                            break;
+                        };
+                        value.push(c);
+                    }
+                    Some(c) => value.push(c),
+                    None => break,
+                }
+                self.pos += 1;
+            }
+            return Err(self.error("unterminated quoted value"));
+        }
+
+        while let Some(c) = self.peek() {
+            if c == ',' || c == ')' {
+                break;
+            }
+            value.push(c);
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::to_sql::ToSqlString;
+
+    #[derive(ToSqlString)]
+    struct Query {
+        email: Option<String>,
+        is_active: Option<bool>,
+    }
+
+    static COLUMNS: [FilterColumn; 3] = [
+        FilterColumn {
+            name: "email",
+            column: "u.email",
+            kind: FilterKind::Text,
+        },
+        FilterColumn {
+            name: "login_count",
+            column: "u.login_count",
+            kind: FilterKind::Int,
+        },
+        FilterColumn {
+            name: "is_active",
+            column: "u.is_active",
+            kind: FilterKind::Bool,
+        },
+    ];
+
+    fn compile(input: &str) -> Result<(String, Vec<String>)> {
+        let mut params = Vec::new();
+        let sql = FilterExpr::parse(input)?.to_sql(&COLUMNS, &mut params)?;
+        let params = params.iter().map(|param| format!("{:?}", param));
+        Ok((sql, params.collect()))
+    }
+
+    fn error(result: Result<impl std::fmt::Debug>) -> String {
+        match result {
+            Err(AppError::ErrorResponse(ErrorResponse {
+                errors: Some(mut errors),
+                ..
+            })) => errors.remove("filter").unwrap().into_owned(),
+            Err(_) => panic!("expected a filter error"),
+            Ok(value) => panic!("expected an error, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn quoted_values_keep_separators_and_escapes() {
+        let (sql, params) =
+            compile(r#"email.eq."a,b)\"c\\d",is_active.eq.true"#)
+                .ok()
+                .unwrap();
+        assert_eq!(sql, "(u.email = $1 AND u.is_active = $2)");
+        assert_eq!(params, [r#""a,b)\"c\\d""#, "true"]);
+
+        assert!(error(compile(r#"email.eq."open"#))
+            .starts_with("unterminated quoted value"));
+        assert!(error(compile(r#"email.eq."a"b"#))
+            .starts_with("unexpected character"));
+    }
+
+    #[test]
+    fn like_patterns_are_escaped() {
+        let (sql, params) = compile("or(email.prefix.a_b,email.ilike.50%)")
+            .ok()
+            .unwrap();
+        assert_eq!(sql, "(u.email ILIKE $1 OR u.email ILIKE $2)");
+        assert_eq!(params, [r#""a\\_b%""#, r#""%50\\%%""#]);
+    }
+
+    #[test]
+    fn in_splits_values_on_pipes() {
+        let (sql, params) =
+            compile("and(email.in.a@x.io|b@x.io,login_count.in.1|2)")
+                .ok()
+                .unwrap();
+        assert_eq!(sql, "(u.email = ANY($1) AND u.login_count = ANY($2))");
+        assert_eq!(params, [r#"["a@x.io", "b@x.io"]"#, "[1, 2]"]);
+        assert_eq!(
+            error(compile("login_count.in.1|x")),
+            "invalid value for `login_count`"
+        );
+    }
+
+    #[test]
+    fn values_are_typed_by_column() {
+        let (sql, params) =
+            compile("not(login_count.gte.3),email.is_null.false")
+                .ok()
+                .unwrap();
+        assert_eq!(sql, "(NOT (u.login_count >= $1) AND u.email IS NOT NULL)");
+        assert_eq!(params, ["3"]);
+        assert_eq!(
+            error(compile("login_count.eq.99999999999")),
+            "invalid value for `login_count`"
+        );
+        assert_eq!(
+            error(compile("is_active.ilike.x")),
+            "invalid value for `is_active`"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_and_operators_are_rejected() {
+        assert_eq!(error(compile("password.eq.x")), "unknown field `password`");
+        assert!(error(compile("email.like.x")).starts_with("unknown operator"));
+        assert!(error(compile("xor(email.eq.x)")).starts_with("unknown group"));
+    }
+
+    #[test]
+    fn depth_and_condition_limits() {
+        let nested = format!(
+            "{}email.eq.x{}",
+            "not(".repeat(MAX_DEPTH + 1),
+            ")".repeat(MAX_DEPTH + 1)
+        );
+        assert!(error(compile(&nested))
+            .starts_with("expression is nested too deeply"));
+        let allowed = format!(
+            "{}email.eq.x{}",
+            "not(".repeat(MAX_DEPTH),
+            ")".repeat(MAX_DEPTH)
+        );
+        assert!(compile(&allowed).is_ok());
+
+        // JSON expressions skip the parser, the compiler enforces it too
+        let mut expr: FilterExpr = serde_json::from_value(serde_json::json!(
+            {"field": "email", "op": "eq", "value": "x"}
+        ))
+        .unwrap();
+        for _ in 0..=MAX_DEPTH {
+            expr = FilterExpr::Not {
+                not: Box::new(expr),
+            };
+        }
+        assert!(error(expr.to_sql(&COLUMNS, &mut Vec::new()))
+            .starts_with("expression is nested too deeply"));
+
+        let conditions = vec!["is_active.eq.true"; MAX_CONDITIONS];
+        assert!(compile(&conditions.join(",")).is_ok());
+        let conditions = vec!["is_active.eq.true"; MAX_CONDITIONS + 1];
+        assert_eq!(
+            error(compile(&conditions.join(","))),
+            "too many conditions"
+        );
+    }
+
+    #[test]
+    fn params_continue_after_as_sql_string() {
+        let query = Query {
+            email: Some("ann".to_string()),
+            is_active: None,
+        };
+        let (mut sql, mut params) = query.as_sql_string("ILIKE", "AND");
+        FilterExpr::parse("or(email.prefix.b,is_active.eq.false)")
+            .ok()
+            .unwrap()
+            .push_where(&mut sql, Query::FILTER_COLUMNS, &mut params)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            sql,
+            "WHERE email ILIKE $1 AND (email ILIKE $2 OR is_active = $3) "
+        );
+        let params: Vec<String> =
+            params.iter().map(|param| format!("{:?}", param)).collect();
+        assert_eq!(params, [r#""%ann%""#, r#""b%""#, "false"]);
+    }
+}
@@ -0,0 +1,14 @@
+pub use macros::FromRow;
+use tokio_postgres::Row;
+
+use crate::common::to_sql::SqlTypes;
+
+// Build a value from a row by column name, fields may borrow from the row
+pub trait FromRow<'a>: SqlTypes + Sized {
+    fn from_row(row: &'a Row) -> Result<Self, Self::Error>;
+
+    #[allow(dead_code)] // only called for `#[column(try_from)]` fields
+    fn invalid_column(column: &str) -> Self::Error {
+        <Self as SqlTypes>::invalid_column(column)
+    }
+}
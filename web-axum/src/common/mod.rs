@@ -1,5 +1,10 @@
 pub mod error;
+pub mod etag;
 pub mod extractor;
+pub mod filter;
+pub mod from_row;
+pub mod patch;
 pub mod response;
+pub mod sort;
 pub mod to_sql;
 pub mod utils;
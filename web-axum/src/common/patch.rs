@@ -0,0 +1,41 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use validator::Validate;
+
+use crate::common::error::{AppError, Result};
+use crate::common::response::ErrorResponse;
+
+// RFC 7396 JSON merge patch
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// Apply `patch` on top of `current` and validate the merged document
+pub fn apply_merge_patch<T>(current: &T, patch: &Value) -> Result<T>
+where
+    T: Serialize + DeserializeOwned + Validate,
+{
+    let mut document =
+        serde_json::to_value(current).map_err(|_| AppError::UnexpectedError)?;
+    merge_patch(&mut document, patch);
+    let merged: T = serde_json::from_value(document).map_err(|_| {
+        AppError::from(ErrorResponse::create_error("Invalid patch document"))
+    })?;
+    merged.validate()?;
+    Ok(merged)
+}
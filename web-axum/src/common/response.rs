@@ -19,6 +19,8 @@ pub struct PaginationOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub next: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(range(min = 1, max = 25, message = "invalid range value"))]
     #[serde(default = "default_pagination_limit")]
     pub limit: Option<u8>,
@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use validator::Validate;
+
+use crate::common::error::{AppError, Result};
+use crate::common::filter::{FilterColumn, FilterKind};
+use crate::common::response::ErrorResponse;
+
+// Unique tie breaker appended to every sort so keyset pagination never
+// skips or repeats rows sharing the same sort values
+static ID_COLUMN: FilterColumn = FilterColumn {
+    name: "id",
+    column: "id",
+    kind: FilterKind::Text,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SortOptions {
+    // e.g. `-create_at,email`, a leading `-` sorts descending
+    #[validate(length(max = 200, message = "invalid field length"))]
+    pub sort: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey {
+    pub column: &'static FilterColumn,
+    pub descending: bool,
+}
+
+fn sort_error(message: String) -> AppError {
+    AppError::from(ErrorResponse {
+        errors: Some(HashMap::from([(
+            "sort".to_string(),
+            Cow::Owned(message),
+        )])),
+        error: None,
+    })
+}
+
+impl SortOptions {
+    // Sort keys resolved through the `columns` whitelist, `id DESC` when
+    // no sort is given
+    pub fn keys(
+        &self,
+        columns: &'static [FilterColumn],
+    ) -> Result<Vec<SortKey>> {
+        let mut keys: Vec<SortKey> = Vec::new();
+        for item in self.sort.as_deref().unwrap_or_default().split(',') {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+            let (name, descending) = match item.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (item, false),
+            };
+            let column = columns
+                .iter()
+                .find(|column| column.name == name)
+                .ok_or_else(|| {
+                    sort_error(format!("`{}` is not sortable", name))
+                })?;
+            if keys.iter().any(|key| key.column.name == column.name) {
+                return Err(sort_error(format!("`{}` sorted twice", name)));
+            }
+            keys.push(SortKey { column, descending });
+        }
+
+        if !keys.iter().any(|key| key.column.name == ID_COLUMN.name) {
+            // follow the direction of the last key so a single index scan
+            // can serve the order
+            let descending = keys.last().is_none_or(|key| key.descending);
+            keys.push(SortKey {
+                column: &ID_COLUMN,
+                descending,
+            });
+        }
+        Ok(keys)
+    }
+}
+
+pub fn order_by(keys: &[SortKey]) -> String {
+    keys.iter()
+        .map(|key| {
+            format!(
+                "{} {}",
+                key.column.column,
+                if key.descending { "DESC" } else { "ASC" }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COLUMNS: [FilterColumn; 3] = [
+        FilterColumn {
+            name: "email",
+            column: "u.email",
+            kind: FilterKind::Text,
+        },
+        FilterColumn {
+            name: "create_at",
+            column: "u.create_at",
+            kind: FilterKind::Timestamp,
+        },
+        FilterColumn {
+            name: "id",
+            column: "u.id",
+            kind: FilterKind::Text,
+        },
+    ];
+
+    fn sorted(sort: Option<&str>) -> Result<String> {
+        let options = SortOptions {
+            sort: sort.map(str::to_string),
+        };
+        Ok(order_by(&options.keys(&COLUMNS)?))
+    }
+
+    fn error(sort: &str) -> String {
+        match sorted(Some(sort)) {
+            Err(AppError::ErrorResponse(ErrorResponse {
+                errors: Some(mut errors),
+                ..
+            })) => errors.remove("sort").unwrap().into_owned(),
+            _ => panic!("expected a sort error for `{}`", sort),
+        }
+    }
+
+    #[test]
+    fn defaults_to_id_descending() {
+        assert_eq!(sorted(None).ok().unwrap(), "id DESC");
+        assert_eq!(sorted(Some(" , ")).ok().unwrap(), "id DESC");
+    }
+
+    #[test]
+    fn id_tie_breaker_follows_the_last_key() {
+        assert_eq!(
+            sorted(Some("-create_at,email")).ok().unwrap(),
+            "u.create_at DESC, u.email ASC, id ASC"
+        );
+        assert_eq!(
+            sorted(Some(" email , -create_at ")).ok().unwrap(),
+            "u.email ASC, u.create_at DESC, id DESC"
+        );
+    }
+
+    #[test]
+    fn explicit_id_is_not_repeated() {
+        assert_eq!(
+            sorted(Some("-id,email")).ok().unwrap(),
+            "u.id DESC, u.email ASC"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_repeated_fields() {
+        assert_eq!(error("password"), "`password` is not sortable");
+        assert_eq!(error("email,-email"), "`email` sorted twice");
+    }
+}
@@ -1,10 +1,95 @@
-pub use macros::ToSqlString;
+pub use macros::{ToSqlInsert, ToSqlString, ToSqlUpdate};
+use tokio_postgres::types::ToSql;
+
+use crate::common::error::AppError;
+use crate::common::filter::{FilterColumn, FilterKind};
+use crate::common::response::ErrorResponse;
+
+// Owned query parameter keeping the Rust type of the value it came from
+pub type SqlParam = Box<dyn ToSql + Sync + Send>;
+
+pub fn as_sql_params(params: &[SqlParam]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+// Escape the LIKE wildcards so user input only matches literally
+pub fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Application types the derived traits are generated against, so the
+// macros don't depend on where they live. Implemented once for every type
+pub trait SqlTypes {
+    type Error: From<tokio_postgres::Error>;
+    type Column: 'static;
+    type ColumnKind;
+
+    // error of a `#[column(try_from)]` conversion failing for `column`
+    #[allow(dead_code)] // only called by derived `FromRow` impls
+    fn invalid_column(column: &str) -> Self::Error;
+    // error of `as_sql_update` without any field present
+    fn nothing_to_update() -> Self::Error;
+}
+
+impl<T: ?Sized> SqlTypes for T {
+    type Error = AppError;
+    type Column = FilterColumn;
+    type ColumnKind = FilterKind;
+
+    fn invalid_column(column: &str) -> AppError {
+        AppError::FatalError(format!("invalid value in column `{}`", column))
+    }
+
+    fn nothing_to_update() -> AppError {
+        AppError::from(ErrorResponse::create_error("No fields to update"))
+    }
+}
+
+pub trait ToSqlString: SqlTypes {
+    // Whitelist of the columns filter expressions may reference
+    const FILTER_COLUMNS: &'static [Self::Column];
+    // Columns accepted by `?sort=`, marked with `#[filter(sortable)]`
+    const SORT_COLUMNS: &'static [Self::Column];
 
-pub trait ToSqlString {
     fn as_sql_string(
         &self,
         operator: &str,
         separator: &str,
-        order_by: &str,
-    ) -> (String, Vec<String>);
+    ) -> (String, Vec<SqlParam>);
+
+    fn escape_like(value: &str) -> String {
+        escape_like(value)
+    }
+}
+
+pub trait ToSqlUpdate: SqlTypes {
+    // `SET` assignments of the present fields with parameters numbered from
+    // `$start`, fails when no field is present
+    fn as_sql_update(
+        &self,
+        start: usize,
+    ) -> Result<(String, Vec<&(dyn ToSql + Sync)>), Self::Error>;
+
+    fn nothing_to_update() -> Self::Error {
+        <Self as SqlTypes>::nothing_to_update()
+    }
+}
+
+pub trait ToSqlInsert {
+    const TABLE: &'static str;
+    const COLUMNS: &'static [&'static str];
+
+    // Append the `($1, $2, ...)` values tuple of this row, numbering the
+    // parameters after the ones already in `params`
+    fn push_values<'a>(
+        &'a self,
+        query: &mut String,
+        params: &mut Vec<&'a (dyn ToSql + Sync)>,
+    );
 }
@@ -2,11 +2,58 @@ use base62;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::env;
+use tracing::warn;
 use uuid::Uuid;
 
 pub static EMAIL_SUFFIX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"\.[a-zA-Z]{2,}$").unwrap());
 
+pub static USERNAME_FORMAT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[a-zA-Z0-9]([a-zA-Z0-9_.]*[a-zA-Z0-9])?$").unwrap()
+});
+
+// language with optional region, e.g. `en` or `en-US`
+pub static LOCALE_FORMAT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-z]{2,3}(-[A-Z]{2})?$").unwrap());
+
+// IANA time zone name, e.g. `UTC` or `Asia/Jakarta`
+pub static TIMEZONE_FORMAT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[A-Za-z][A-Za-z0-9_+\-]*(/[A-Za-z0-9_+\-]+)*$").unwrap()
+});
+
+pub static RESERVED_USERNAMES: [&str; 14] = [
+    "admin",
+    "administrator",
+    "api",
+    "auth",
+    "help",
+    "list",
+    "me",
+    "null",
+    "root",
+    "settings",
+    "support",
+    "system",
+    "undefined",
+    "user",
+];
+
+// Days a user has to wait between two username changes
+pub static USERNAME_CHANGE_COOLDOWN_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("USERNAME_CHANGE_COOLDOWN_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
+// Days a previous username keeps resolving to its user
+pub static USERNAME_ALIAS_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("USERNAME_ALIAS_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
 pub static PASSWORD_ITERATION: Lazy<u32> = Lazy::new(|| {
     env::var("PASSWORD_ITERATION")
         .unwrap_or_else(|_| "10000".to_string())
@@ -14,10 +61,128 @@ pub static PASSWORD_ITERATION: Lazy<u32> = Lazy::new(|| {
         .unwrap()
 });
 
+// Days a soft deleted user is kept before being purged
+pub static DELETED_USER_RETENTION_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("DELETED_USER_RETENTION_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
+// Seconds between each purge run of soft deleted users
+pub static USER_PURGE_INTERVAL: Lazy<u64> = Lazy::new(|| {
+    env::var("USER_PURGE_INTERVAL")
+        .unwrap_or_else(|_| "3600".to_string())
+        .parse::<u64>()
+        .unwrap()
+});
+
+// Seconds a login session stays valid
+pub static SESSION_TTL: Lazy<i64> = Lazy::new(|| {
+    env::var("SESSION_TTL")
+        .unwrap_or_else(|_| "604800".to_string())
+        .parse::<i64>()
+        .unwrap()
+});
+
+// Max bytes of an uploaded profile image
+pub static AVATAR_MAX_SIZE: Lazy<usize> = Lazy::new(|| {
+    env::var("AVATAR_MAX_SIZE")
+        .unwrap_or_else(|_| "5242880".to_string())
+        .parse::<usize>()
+        .unwrap()
+});
+
+// Max bytes of a bulk user import body
+pub static IMPORT_MAX_SIZE: Lazy<usize> = Lazy::new(|| {
+    env::var("IMPORT_MAX_SIZE")
+        .unwrap_or_else(|_| "10485760".to_string())
+        .parse::<usize>()
+        .unwrap()
+});
+
+// Rows per multi-row INSERT of a bulk user import
+pub static IMPORT_BATCH_SIZE: Lazy<usize> = Lazy::new(|| {
+    env::var("IMPORT_BATCH_SIZE")
+        .unwrap_or_else(|_| "500".to_string())
+        .parse::<usize>()
+        .unwrap()
+});
+
+// Days a personal data archive stays downloadable
+pub static DATA_EXPORT_TTL_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("DATA_EXPORT_TTL_DAYS")
+        .unwrap_or_else(|_| "7".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
+// Days between an erasure request and the anonymization of the account
+pub static ERASURE_COOLING_OFF_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("ERASURE_COOLING_OFF_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
+// Rows fetched from the cursor per chunk of a user export
+pub static EXPORT_BATCH_SIZE: Lazy<i32> = Lazy::new(|| {
+    env::var("EXPORT_BATCH_SIZE")
+        .unwrap_or_else(|_| "1000".to_string())
+        .parse::<i32>()
+        .unwrap()
+});
+
+// Max bytes of a request body unless a route sets its own limit
+pub static REQUEST_BODY_LIMIT: Lazy<usize> = Lazy::new(|| {
+    env::var("REQUEST_BODY_LIMIT")
+        .unwrap_or_else(|_| "100000".to_string())
+        .parse::<usize>()
+        .unwrap()
+});
+
+// Times a request is replayed after a serialization failure
+pub static TRANSACTION_MAX_RETRIES: Lazy<u32> = Lazy::new(|| {
+    env::var("TRANSACTION_MAX_RETRIES")
+        .unwrap_or_else(|_| "3".to_string())
+        .parse::<u32>()
+        .unwrap()
+});
+
+// Same variable and default as dbmate, both read the same files
+pub static MIGRATIONS_DIR: Lazy<String> = Lazy::new(|| {
+    env::var("DBMATE_MIGRATIONS_DIR")
+        .unwrap_or_else(|_| "./db/migrations".to_string())
+});
+
+// Apply pending migrations before serving requests
+pub static MIGRATE_ON_STARTUP: Lazy<bool> = Lazy::new(|| {
+    env::var("MIGRATE_ON_STARTUP")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+});
+
+// Key signing pagination cursors, without it a random key is used and
+// cursors stop working across restarts and instances
+pub static CURSOR_SECRET: Lazy<String> =
+    Lazy::new(|| match env::var("CURSOR_SECRET") {
+        Ok(secret) => secret,
+        Err(_) => {
+            warn!("CURSOR_SECRET not set, cursors use a random key");
+            session_token()
+        }
+    });
+
 pub fn uuid7_b62() -> String {
     base62::encode(Uuid::now_v7().as_u128())
 }
 
+// random session token, unlike uuid7 it does not leak creation time
+pub fn session_token() -> String {
+    base62::encode(Uuid::new_v4().as_u128())
+        + &base62::encode(Uuid::new_v4().as_u128())
+}
+
 pub mod Password {
     use pbkdf2::password_hash::{PasswordVerifier, SaltString};
     use pbkdf2::{
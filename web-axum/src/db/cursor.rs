@@ -0,0 +1,156 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::common::error::{AppError, Result};
+use crate::common::response::ErrorResponse;
+use crate::common::sort::{order_by, SortKey};
+use crate::common::utils::CURSOR_SECRET;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keyset position, sent to clients as `base64(json).base64(hmac)` so it
+// can't be forged into arbitrary parameters. It only tells where a page
+// starts, the filters of each request still apply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    // rows before the position rather than after it
+    #[serde(rename = "b")]
+    pub backward: bool,
+    // sort the position belongs to, a cursor is only valid for it
+    #[serde(rename = "o")]
+    pub order: String,
+    // sort key values of the row at the position
+    #[serde(rename = "k")]
+    pub values: Vec<Value>,
+}
+
+pub fn invalid_cursor() -> AppError {
+    AppError::from(ErrorResponse::create_error("Invalid pagination cursor"))
+}
+
+fn signature(payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(CURSOR_SECRET.as_bytes()).unwrap();
+    mac.update(payload);
+    mac
+}
+
+impl Cursor {
+    pub fn new(keys: &[SortKey], values: Vec<Value>, backward: bool) -> Self {
+        Self {
+            backward,
+            order: order_by(keys),
+            values,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let payload = serde_json::to_vec(self).unwrap();
+        let tag = signature(&payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(tag)
+        )
+    }
+
+    // Verify and decode a cursor issued for the same sort as `keys`
+    pub fn decode(token: &str, keys: &[SortKey]) -> Result<Self> {
+        let (payload, tag) =
+            token.split_once('.').ok_or_else(invalid_cursor)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| invalid_cursor())?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid_cursor())?;
+        signature(&payload)
+            .verify_slice(&tag)
+            .map_err(|_| invalid_cursor())?;
+
+        let cursor: Self =
+            serde_json::from_slice(&payload).map_err(|_| invalid_cursor())?;
+        if cursor.order != order_by(keys) || cursor.values.len() != keys.len() {
+            return Err(invalid_cursor());
+        }
+        Ok(cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::filter::{FilterColumn, FilterKind};
+    use serde_json::json;
+
+    static CREATE_AT: FilterColumn = FilterColumn {
+        name: "create_at",
+        column: "create_at",
+        kind: FilterKind::Timestamp,
+    };
+    static ID: FilterColumn = FilterColumn {
+        name: "id",
+        column: "id",
+        kind: FilterKind::Text,
+    };
+
+    fn keys(descending: bool) -> [SortKey; 2] {
+        [
+            SortKey {
+                column: &CREATE_AT,
+                descending,
+            },
+            SortKey {
+                column: &ID,
+                descending,
+            },
+        ]
+    }
+
+    fn values() -> Vec<Value> {
+        vec![
+            json!("2024-05-01T10:00:00.000000Z"),
+            json!("0MzHqXn2Wr1Yb4"),
+        ]
+    }
+
+    #[test]
+    fn round_trip_both_directions() {
+        for backward in [false, true] {
+            let token = Cursor::new(&keys(true), values(), backward).encode();
+            let cursor = Cursor::decode(&token, &keys(true)).ok().unwrap();
+            assert_eq!(cursor.backward, backward);
+            assert_eq!(cursor.values, values());
+        }
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected() {
+        let token = Cursor::new(&keys(true), values(), false).encode();
+        let (_, tag) = token.split_once('.').unwrap();
+
+        let mut forged = Cursor::new(&keys(true), values(), false);
+        forged.values[1] = json!("' OR 1=1 --");
+        let payload = serde_json::to_vec(&forged).unwrap();
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), tag);
+        assert!(Cursor::decode(&forged, &keys(true)).is_err());
+
+        let mut flipped = token.clone().into_bytes();
+        let last = flipped.len() - 1;
+        flipped[last] = if flipped[last] == b'A' { b'B' } else { b'A' };
+        let flipped = String::from_utf8(flipped).unwrap();
+        assert!(Cursor::decode(&flipped, &keys(true)).is_err());
+
+        for token in ["", "no-dot", "a.b", &token[..token.len() - 4]] {
+            assert!(Cursor::decode(token, &keys(true)).is_err());
+        }
+    }
+
+    #[test]
+    fn cursors_only_fit_their_sort() {
+        let token = Cursor::new(&keys(true), values(), false).encode();
+        assert!(Cursor::decode(&token, &keys(false)).is_err());
+        assert!(Cursor::decode(&token, &keys(true)[1..]).is_err());
+    }
+}
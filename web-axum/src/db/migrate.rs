@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use tokio_postgres::Client;
+
+use crate::common::utils::MIGRATIONS_DIR;
+use crate::db::extractors::ConnectionPool;
+
+// Key of the session level advisory lock held while migrating, so
+// instances starting together apply each migration once
+const MIGRATION_LOCK_ID: i64 = 0x6462_6d61_7465;
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Io(std::io::Error),
+    Parse(String),
+    Pool(String),
+    Database(tokio_postgres::Error),
+}
+
+impl Display for MigrateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read migrations: {}", err),
+            Self::Parse(message) => write!(f, "{}", message),
+            Self::Pool(message) => write!(f, "cannot connect: {}", message),
+            Self::Database(err) => write!(f, "database error: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for MigrateError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<tokio_postgres::Error> for MigrateError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+type Result<T> = core::result::Result<T, MigrateError>;
+
+#[derive(Debug, Default)]
+struct Section {
+    sql: String,
+    transaction: bool,
+}
+
+// A dbmate migration file: `<version>_<name>.sql` with `-- migrate:up` and
+// `-- migrate:down` sections, each optionally marked `transaction:false`
+#[derive(Debug)]
+pub struct Migration {
+    pub version: String,
+    pub file_name: String,
+    up: Section,
+    down: Section,
+}
+
+impl Migration {
+    fn parse(file_name: &str, contents: &str) -> Result<Self> {
+        let version = file_name
+            .split_once('_')
+            .map(|(version, _)| version)
+            .filter(|version| version.chars().all(|c| c.is_ascii_digit()))
+            .ok_or_else(|| {
+                MigrateError::Parse(format!("{}: invalid file name", file_name))
+            })?;
+
+        let mut up: Option<Section> = None;
+        let mut down: Option<Section> = None;
+        let mut current: Option<&mut Section> = None;
+        for line in contents.lines() {
+            let directive = line
+                .strip_prefix("--")
+                .map(str::trim_start)
+                .and_then(|line| line.strip_prefix("migrate:"));
+            let Some(directive) = directive else {
+                if let Some(section) = current.as_mut() {
+                    section.sql.push_str(line);
+                    section.sql.push('\n');
+                }
+                continue;
+            };
+
+            let mut options = directive.split_whitespace();
+            let target = match options.next() {
+                Some("up") => &mut up,
+                Some("down") => &mut down,
+                _ => {
+                    return Err(MigrateError::Parse(format!(
+                        "{}: unknown directive `{}`",
+                        file_name,
+                        line.trim()
+                    )))
+                }
+            };
+            if target.is_some() {
+                return Err(MigrateError::Parse(format!(
+                    "{}: duplicate `{}` section",
+                    file_name,
+                    line.trim()
+                )));
+            }
+            let transaction =
+                !options.any(|option| option == "transaction:false");
+            current = Some(target.insert(Section {
+                sql: String::new(),
+                transaction,
+            }));
+        }
+
+        let up = up.ok_or_else(|| {
+            MigrateError::Parse(format!(
+                "{}: missing `-- migrate:up`",
+                file_name
+            ))
+        })?;
+        Ok(Self {
+            version: version.to_string(),
+            file_name: file_name.to_string(),
+            up,
+            down: down.unwrap_or_default(),
+        })
+    }
+}
+
+// Every migration found in `dir`, ordered by version
+pub fn load_migrations(dir: &Path) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("sql") {
+            continue;
+        }
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let contents = fs::read_to_string(&path)?;
+        migrations.push(Migration::parse(&file_name, &contents)?);
+    }
+    migrations.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(migrations)
+}
+
+async fn applied_versions(client: &Client) -> Result<HashSet<String>> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations \
+            (version varchar(128) PRIMARY KEY)",
+        )
+        .await?;
+    let rows = client
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+async fn execute(
+    client: &mut Client,
+    section: &Section,
+    record: &str,
+    version: &str,
+) -> Result<()> {
+    if section.transaction {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(&section.sql).await?;
+        transaction.execute(record, &[&version]).await?;
+        transaction.commit().await?;
+    } else {
+        client.batch_execute(&section.sql).await?;
+        client.execute(record, &[&version]).await?;
+    }
+    Ok(())
+}
+
+// Apply every pending migration, returning the applied file names
+async fn migrate_up(
+    client: &mut Client,
+    migrations: &[Migration],
+) -> Result<Vec<String>> {
+    let applied = applied_versions(client).await?;
+    let mut names = Vec::new();
+    for migration in migrations {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        execute(
+            client,
+            &migration.up,
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &migration.version,
+        )
+        .await?;
+        names.push(migration.file_name.clone());
+    }
+    Ok(names)
+}
+
+// Roll back the most recently applied migration
+async fn migrate_down(
+    client: &mut Client,
+    migrations: &[Migration],
+) -> Result<Option<String>> {
+    let applied = applied_versions(client).await?;
+    let Some(migration) = migrations
+        .iter()
+        .rev()
+        .find(|migration| applied.contains(&migration.version))
+    else {
+        return Ok(None);
+    };
+    if migration.down.sql.trim().is_empty() {
+        return Err(MigrateError::Parse(format!(
+            "{}: no `-- migrate:down` section",
+            migration.file_name
+        )));
+    }
+    execute(
+        client,
+        &migration.down,
+        "DELETE FROM schema_migrations WHERE version = $1",
+        &migration.version,
+    )
+    .await?;
+    Ok(Some(migration.file_name.clone()))
+}
+
+async fn lock(client: &Client) -> Result<()> {
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_ID])
+        .await?;
+    Ok(())
+}
+
+async fn unlock(client: &Client) -> Result<()> {
+    client
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_ID])
+        .await?;
+    Ok(())
+}
+
+// Apply pending migrations, used on startup when `MIGRATE_ON_STARTUP` is set
+pub async fn up(pool: &ConnectionPool) -> Result<Vec<String>> {
+    let migrations = load_migrations(Path::new(&*MIGRATIONS_DIR))?;
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|err| MigrateError::Pool(err.to_string()))?;
+
+    lock(&conn).await?;
+    let applied = migrate_up(&mut conn, &migrations).await;
+    unlock(&conn).await?;
+    applied
+}
+
+// Entry point of the `migrate up|down|status` subcommands
+pub async fn run_command(
+    pool: &ConnectionPool,
+    command: Option<&str>,
+) -> Result<()> {
+    let migrations = load_migrations(Path::new(&*MIGRATIONS_DIR))?;
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|err| MigrateError::Pool(err.to_string()))?;
+
+    lock(&conn).await?;
+    let result = match command {
+        Some("up") => migrate_up(&mut conn, &migrations).await.map(|applied| {
+            for name in &applied {
+                println!("Applied: {}", name);
+            }
+            if applied.is_empty() {
+                println!("Nothing to apply");
+            }
+        }),
+        Some("down") => {
+            migrate_down(&mut conn, &migrations)
+                .await
+                .map(|rolled_back| match rolled_back {
+                    Some(name) => println!("Rolled back: {}", name),
+                    None => println!("Nothing to roll back"),
+                })
+        }
+        Some("status") => applied_versions(&conn).await.map(|applied| {
+            let mut pending = 0;
+            for migration in &migrations {
+                if applied.contains(&migration.version) {
+                    println!("[X] {}", migration.file_name);
+                } else {
+                    pending += 1;
+                    println!("[ ] {}", migration.file_name);
+                }
+            }
+            println!();
+            println!("Applied: {}", migrations.len() - pending);
+            println!("Pending: {}", pending);
+        }),
+        _ => Err(MigrateError::Parse(
+            "usage: migrate <up|down|status>".to_string(),
+        )),
+    };
+    unlock(&conn).await?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(file_name: &str, contents: &str) -> String {
+        match Migration::parse(file_name, contents) {
+            Err(MigrateError::Parse(message)) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_sections() {
+        let migration = Migration::parse(
+            "20240101000000_create_users.sql",
+            "-- migrate:up\ncreate table users ();\n\n\
+            -- migrate:down\ndrop table users;\n",
+        )
+        .unwrap();
+        assert_eq!(migration.version, "20240101000000");
+        assert_eq!(migration.file_name, "20240101000000_create_users.sql");
+        assert_eq!(migration.up.sql, "create table users ();\n\n");
+        assert!(migration.up.transaction);
+        assert_eq!(migration.down.sql, "drop table users;\n");
+        assert!(migration.down.transaction);
+    }
+
+    #[test]
+    fn parse_without_transaction() {
+        let migration = Migration::parse(
+            "20240102000000_index.sql",
+            "-- migrate:up transaction:false\n\
+            create index concurrently users_email on users (email);\n\
+            --migrate:down\ndrop index users_email;\n",
+        )
+        .unwrap();
+        assert!(!migration.up.transaction);
+        assert!(migration.down.transaction);
+        assert_eq!(migration.down.sql, "drop index users_email;\n");
+    }
+
+    #[test]
+    fn parse_missing_down_section() {
+        let migration = Migration::parse(
+            "20240103000000_seed.sql",
+            "-- a comment before any section\n-- migrate:up\nselect 1;\n",
+        )
+        .unwrap();
+        assert_eq!(migration.up.sql, "select 1;\n");
+        assert!(migration.down.sql.is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_files() {
+        assert!(parse_error("create_users.sql", "-- migrate:up\n")
+            .contains("invalid file name"));
+        assert!(parse_error("2024_x.sql", "-- migrate:down\nselect 1;\n")
+            .contains("missing `-- migrate:up`"));
+        assert!(parse_error("2024_x.sql", "-- migrate:up\n-- migrate:up\n")
+            .contains("duplicate"));
+        assert!(parse_error("2024_x.sql", "-- migrate:sideways\n")
+            .contains("unknown directive"));
+    }
+
+    #[test]
+    fn load_orders_by_version() {
+        let dir = std::env::temp_dir()
+            .join(format!("migrate-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["20240301000000_c.sql", "20240101000000_a.sql"] {
+            fs::write(dir.join(name), "-- migrate:up\nselect 1;\n").unwrap();
+        }
+        fs::write(dir.join("20240201000000_b.sql"), "-- migrate:up\n").unwrap();
+        fs::write(dir.join("README.md"), "not a migration").unwrap();
+
+        let migrations = load_migrations(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let versions: Vec<String> = migrations
+            .unwrap()
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect();
+        assert_eq!(
+            versions,
+            ["20240101000000", "20240201000000", "20240301000000"]
+        );
+    }
+
+    #[test]
+    fn repository_migrations_parse() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("db/migrations");
+        let migrations = load_migrations(&dir).unwrap();
+        assert!(!migrations.is_empty());
+        for pair in migrations.windows(2) {
+            assert_ne!(pair[0].version, pair[1].version);
+        }
+    }
+}
@@ -1,2 +1,5 @@
+pub mod cursor;
 pub mod extractors;
+pub mod migrate;
 pub mod query;
+pub mod transaction;
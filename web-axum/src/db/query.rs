@@ -1,33 +1,99 @@
+use crate::common::error::{AppError, Result};
+use crate::common::filter::{to_param, FilterKind};
+use crate::common::from_row::FromRow;
 use crate::common::response::PaginationOptions;
+use crate::common::sort::{order_by, SortKey};
+use crate::common::to_sql::{as_sql_params, SqlParam, ToSqlInsert};
+use crate::db::cursor::{invalid_cursor, Cursor};
 use crate::db::extractors::ConnectionPooled;
-use tokio_postgres::{Error, Row};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{GenericClient, Row};
 
 static DEFAULT_PAGINATION: u8 = 10;
-static DEFAULT_ORDER_BY: &'static str = "ORDER BY id DESC ";
 
 pub struct Builder;
 
-type QueryResult<T> = Result<T, Error>;
+// Sort key values of a row, in the order of `keys`
+fn cursor_values(row: &Row, keys: &[SortKey]) -> Result<Vec<Value>> {
+    keys.iter()
+        .map(|key| {
+            let name = key.column.name;
+            Ok(match key.column.kind {
+                FilterKind::Text => json!(row.try_get::<_, String>(name)?),
+                FilterKind::Bool => json!(row.try_get::<_, bool>(name)?),
+                FilterKind::SmallInt => json!(row.try_get::<_, i16>(name)?),
+                FilterKind::Int => json!(row.try_get::<_, i32>(name)?),
+                FilterKind::BigInt => json!(row.try_get::<_, i64>(name)?),
+                FilterKind::Double => json!(row.try_get::<_, f64>(name)?),
+                FilterKind::Timestamp => {
+                    let value: DateTime<Utc> = row.try_get(name)?;
+                    json!(value.to_rfc3339_opts(SecondsFormat::Micros, true))
+                }
+            })
+        })
+        .collect()
+}
+
+// Rows strictly after the cursor in `keys` order, expanded as
+// `a > $1 OR (a = $1 AND b > $2) ...` since directions may differ per key
+fn keyset_condition(keys: &[SortKey], start: usize) -> String {
+    let conditions = keys
+        .iter()
+        .enumerate()
+        .map(|(idx, key)| {
+            let mut parts = keys[..idx]
+                .iter()
+                .enumerate()
+                .map(|(prev, key)| {
+                    format!("{} = ${}", key.column.column, start + prev)
+                })
+                .collect::<Vec<_>>();
+            parts.push(format!(
+                "{} {} ${}",
+                key.column.column,
+                if key.descending { "<" } else { ">" },
+                start + idx
+            ));
+            format!("({})", parts.join(" AND "))
+        })
+        .collect::<Vec<_>>();
+    format!("({})", conditions.join(" OR "))
+}
+
+// The `next` or `prev` cursor of a request, asking for both is ambiguous
+fn cursor_token(pagination: &PaginationOptions) -> Result<Option<&str>> {
+    match (pagination.next.as_deref(), pagination.prev.as_deref()) {
+        (Some(_), Some(_)) => Err(invalid_cursor()),
+        (next, prev) => Ok(next.or(prev)),
+    }
+}
+
+// One page of rows with the cursors of its neighbouring pages
+pub struct Page {
+    pub rows: Vec<Row>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
 
 impl Builder {
+    // Page of rows ordered by `keys`, starting after the `next` cursor or
+    // ending before the `prev` one. Sort columns must be NOT NULL and
+    // selected under their name for the cursors to be built.
     pub async fn query(
         con: &ConnectionPooled,
         query: &mut String,
-        query_value: &mut Vec<String>,
-        order_by: Option<&str>,
+        query_value: &mut Vec<SqlParam>,
+        keys: &[SortKey],
         pagination: Option<&PaginationOptions>,
-    ) -> QueryResult<(Vec<Row>, bool)> {
-        let mut counter = query_value.len();
+    ) -> Result<Page> {
+        let has_where = query.contains("WHERE");
         let mut pagination_limit = DEFAULT_PAGINATION;
+        let mut cursor = None;
         if let Some(value) = pagination {
-            if let Some(next) = value.next.as_ref() {
-                *query += format!(
-                    " {} id < ${} ",
-                    if counter == 0 { "WHERE" } else { "AND" },
-                    counter + 1,
-                )
-                .as_str();
-                query_value.push(next.to_string());
+            if let Some(token) = cursor_token(value)? {
+                cursor = Some(Cursor::decode(token, keys)?);
             }
 
             if let Some(limit) = value.limit {
@@ -35,26 +101,273 @@ impl Builder {
             }
         }
 
-        if let Some(order_by) = order_by {
-            *query += format!("ORDER BY {} ", order_by).as_str();
-        } else {
-            *query += DEFAULT_ORDER_BY;
+        // backward pages scan in the reverse order and are flipped after
+        let backward = cursor.as_ref().is_some_and(|cursor| cursor.backward);
+        let scan_keys = keys
+            .iter()
+            .map(|key| SortKey {
+                descending: key.descending != backward,
+                ..*key
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(cursor) = cursor.as_ref() {
+            let start = query_value.len() + 1;
+            for (key, value) in keys.iter().zip(cursor.values.iter()) {
+                query_value.push(
+                    to_param(key.column, value)
+                        .map_err(|_| invalid_cursor())?,
+                );
+            }
+            *query += format!(
+                " {} {} ",
+                if has_where { "AND" } else { "WHERE" },
+                keyset_condition(&scan_keys, start),
+            )
+            .as_str();
         }
 
+        *query += format!("ORDER BY {} ", order_by(&scan_keys)).as_str();
         *query += format!("LIMIT {}", pagination_limit + 1).as_str();
 
-        let sql_value: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> =
-            query_value
-                .iter()
-                .map(|x| x as &(dyn tokio_postgres::types::ToSql + Sync))
-                .collect();
+        let mut rows = con
+            .query(query.as_str(), &as_sql_params(query_value))
+            .await?;
+        let has_more = rows.len() > pagination_limit as usize;
+        rows.truncate(pagination_limit as usize);
+        if backward {
+            rows.reverse();
+        }
 
-        let rows = con.query(query.as_str(), &sql_value).await?;
-        let has_next = if rows.len() > pagination_limit as usize {
-            true
+        let (has_prev, has_next) = if backward {
+            (has_more, true)
         } else {
-            false
+            (cursor.is_some(), has_more)
         };
-        Ok((rows, has_next))
+        let encode = |row: Option<&Row>, backward: bool| -> Result<_> {
+            Ok(match row {
+                Some(row) => {
+                    let values = cursor_values(row, keys)?;
+                    Some(Cursor::new(keys, values, backward).encode())
+                }
+                None => None,
+            })
+        };
+        Ok(Page {
+            next: if has_next {
+                encode(rows.last(), false)?
+            } else {
+                None
+            },
+            prev: if has_prev {
+                encode(rows.first(), true)?
+            } else {
+                None
+            },
+            rows,
+        })
+    }
+}
+
+// Postgres limit of bind parameters in a single statement
+const MAX_BIND_PARAMS: usize = 65535;
+
+// INSERT of one or more rows of a `ToSqlInsert` type
+pub struct InsertBuilder<'a, T> {
+    rows: &'a [T],
+    on_conflict: Option<(&'a str, &'a str)>,
+    returning: Option<&'a str>,
+}
+
+impl<'a, T: ToSqlInsert> InsertBuilder<'a, T> {
+    pub fn new(rows: &'a [T]) -> Self {
+        Self {
+            rows,
+            on_conflict: None,
+            returning: None,
+        }
+    }
+
+    // e.g. `on_conflict("(lower(email))", "DO NOTHING")`
+    pub fn on_conflict(mut self, target: &'a str, action: &'a str) -> Self {
+        self.on_conflict = Some((target, action));
+        self
+    }
+
+    pub fn returning(mut self, columns: &'a str) -> Self {
+        self.returning = Some(columns);
+        self
+    }
+
+    // Most rows a single statement can insert, callers chunk above it
+    pub fn max_rows() -> usize {
+        MAX_BIND_PARAMS / T::COLUMNS.len().max(1)
+    }
+
+    pub fn build(&self) -> Result<(String, Vec<&'a (dyn ToSql + Sync)>)> {
+        if self.rows.is_empty() {
+            return Err(AppError::FatalError("Nothing to insert".to_string()));
+        }
+        if self.rows.len() > Self::max_rows() {
+            return Err(AppError::FatalError(
+                "Too many rows in a single insert".to_string(),
+            ));
+        }
+
+        let mut query = format!(
+            "INSERT INTO {} ({}) VALUES ",
+            T::TABLE,
+            T::COLUMNS.join(", ")
+        );
+        let mut params = Vec::with_capacity(self.rows.len() * T::COLUMNS.len());
+        for (idx, row) in self.rows.iter().enumerate() {
+            if idx > 0 {
+                query += ", ";
+            }
+            row.push_values(&mut query, &mut params);
+        }
+        if let Some((target, action)) = self.on_conflict {
+            query += format!(" ON CONFLICT {} {}", target, action).as_str();
+        }
+        if let Some(columns) = self.returning {
+            query += format!(" RETURNING {}", columns).as_str();
+        }
+        Ok((query, params))
+    }
+
+    // Inserted rows mapped from the `RETURNING` columns
+    pub async fn fetch<C, R>(&self, con: &C) -> Result<Vec<R>>
+    where
+        C: GenericClient,
+        R: for<'r> FromRow<'r, Error = AppError>,
+    {
+        let (query, params) = self.build()?;
+        con.query(query.as_str(), &params)
+            .await?
+            .iter()
+            .map(R::from_row)
+            .collect()
+    }
+
+    pub async fn fetch_one<C, R>(&self, con: &C) -> Result<R>
+    where
+        C: GenericClient,
+        R: for<'r> FromRow<'r, Error = AppError>,
+    {
+        let (query, params) = self.build()?;
+        R::from_row(&con.query_one(query.as_str(), &params).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::filter::FilterColumn;
+
+    static CREATE_AT: FilterColumn = FilterColumn {
+        name: "create_at",
+        column: "u.create_at",
+        kind: FilterKind::Timestamp,
+    };
+    static EMAIL: FilterColumn = FilterColumn {
+        name: "email",
+        column: "u.email",
+        kind: FilterKind::Text,
+    };
+    static ID: FilterColumn = FilterColumn {
+        name: "id",
+        column: "u.id",
+        kind: FilterKind::Text,
+    };
+
+    fn key(column: &'static FilterColumn, descending: bool) -> SortKey {
+        SortKey { column, descending }
+    }
+
+    fn pagination(next: Option<&str>, prev: Option<&str>) -> PaginationOptions {
+        PaginationOptions {
+            has_next: None,
+            next: next.map(str::to_string),
+            prev: prev.map(str::to_string),
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn cursor_token_takes_one_direction() {
+        let none = pagination(None, None);
+        assert_eq!(cursor_token(&none).ok().unwrap(), None);
+        let next = pagination(Some("n"), None);
+        assert_eq!(cursor_token(&next).ok().unwrap(), Some("n"));
+        let prev = pagination(None, Some("p"));
+        assert_eq!(cursor_token(&prev).ok().unwrap(), Some("p"));
+        assert!(cursor_token(&pagination(Some("n"), Some("p"))).is_err());
+    }
+
+    #[test]
+    fn keyset_single_key() {
+        assert_eq!(keyset_condition(&[key(&ID, true)], 1), "((u.id < $1))");
+        assert_eq!(keyset_condition(&[key(&ID, false)], 4), "((u.id > $4))");
+    }
+
+    #[test]
+    fn keyset_expands_mixed_directions() {
+        let keys = [key(&CREATE_AT, true), key(&EMAIL, false), key(&ID, true)];
+        assert_eq!(
+            keyset_condition(&keys, 3),
+            "((u.create_at < $3) \
+            OR (u.create_at = $3 AND u.email > $4) \
+            OR (u.create_at = $3 AND u.email = $4 AND u.id < $5))"
+        );
+    }
+
+    #[derive(ToSqlInsert)]
+    #[sql(table = "items")]
+    struct Item {
+        id: String,
+        #[sql(rename = "label")]
+        name: String,
+        #[sql(default)]
+        rank: Option<i32>,
+        #[sql(skip)]
+        #[allow(dead_code)]
+        note: String,
+    }
+
+    fn item(id: &str, rank: Option<i32>) -> Item {
+        Item {
+            id: id.to_string(),
+            name: format!("item {}", id),
+            rank,
+            note: String::new(),
+        }
+    }
+
+    #[test]
+    fn insert_numbers_params_across_rows() {
+        let items = [item("a", Some(1)), item("b", None)];
+        let (query, params) = InsertBuilder::new(&items)
+            .on_conflict("(id)", "DO NOTHING")
+            .returning("id")
+            .build()
+            .ok()
+            .unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO items (id, label, rank) VALUES ($1, $2, $3), \
+            ($4, $5, DEFAULT) ON CONFLICT (id) DO NOTHING RETURNING id"
+        );
+        assert_eq!(params.len(), 5);
+    }
+
+    #[test]
+    fn insert_stays_under_bind_param_limit() {
+        assert_eq!(InsertBuilder::<Item>::max_rows(), 65535 / 3);
+        let items: Vec<Item> = (0..=InsertBuilder::<Item>::max_rows())
+            .map(|idx| item(&idx.to_string(), None))
+            .collect();
+        assert!(InsertBuilder::new(&items).build().is_err());
+        assert!(InsertBuilder::new(&items[1..]).build().is_ok());
+        assert!(InsertBuilder::<Item>::new(&[]).build().is_err());
     }
 }
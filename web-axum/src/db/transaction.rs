@@ -0,0 +1,181 @@
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    extract::{FromRef, FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::ops::Deref;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::IsolationLevel;
+use tracing::error;
+
+use crate::common::error::{internal_error, AppError, SerializationFailure};
+use crate::common::utils::{REQUEST_BODY_LIMIT, TRANSACTION_MAX_RETRIES};
+use crate::db::extractors::{ConnectionPool, ConnectionPooled};
+
+// Connection with an open transaction, rolled back in the background if
+// dropped before being finished, e.g. when the handler panics
+struct OpenTransaction(Option<ConnectionPooled>);
+
+impl OpenTransaction {
+    async fn finish(
+        mut self,
+        statement: &str,
+    ) -> Result<(), tokio_postgres::Error> {
+        let conn = self.0.take().unwrap();
+        conn.batch_execute(statement).await
+    }
+}
+
+impl Drop for OpenTransaction {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.take() {
+            tokio::spawn(async move {
+                if conn.batch_execute("ROLLBACK").await.is_err() {
+                    error!("Transaction - failed to roll back");
+                }
+            });
+        }
+    }
+}
+
+type Slot = Arc<Mutex<Option<OpenTransaction>>>;
+
+#[derive(Clone)]
+struct TransactionContext {
+    slot: Slot,
+    isolation: IsolationLevel,
+}
+
+#[derive(Clone, Copy)]
+pub struct TransactionConfig {
+    pub isolation: IsolationLevel,
+    pub max_retries: u32,
+}
+
+impl TransactionConfig {
+    pub fn new(isolation: IsolationLevel) -> Self {
+        Self {
+            isolation,
+            max_retries: *TRANSACTION_MAX_RETRIES,
+        }
+    }
+}
+
+fn begin_statement(isolation: IsolationLevel) -> &'static str {
+    match isolation {
+        IsolationLevel::ReadUncommitted => {
+            "BEGIN ISOLATION LEVEL READ UNCOMMITTED"
+        }
+        IsolationLevel::RepeatableRead => {
+            "BEGIN ISOLATION LEVEL REPEATABLE READ"
+        }
+        IsolationLevel::Serializable => "BEGIN ISOLATION LEVEL SERIALIZABLE",
+        _ => "BEGIN ISOLATION LEVEL READ COMMITTED",
+    }
+}
+
+// Connection inside a transaction that the `transaction` middleware
+// commits when the handler responds with a success or redirect status and
+// rolls back otherwise
+pub struct DatabaseTransaction(OwnedMutexGuard<Option<OpenTransaction>>);
+
+impl Deref for DatabaseTransaction {
+    type Target = ConnectionPooled;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().and_then(|open| open.0.as_ref()).unwrap()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DatabaseTransaction
+where
+    ConnectionPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let context = parts
+            .extensions
+            .get::<TransactionContext>()
+            .cloned()
+            .ok_or_else(|| {
+                AppError::FatalError("Transaction layer is missing".to_string())
+            })?;
+
+        let mut slot = context.slot.lock_owned().await;
+        if slot.is_none() {
+            let pool = ConnectionPool::from_ref(state);
+            let conn = pool.get_owned().await.map_err(internal_error)?;
+            conn.batch_execute(begin_statement(context.isolation))
+                .await?;
+            *slot = Some(OpenTransaction(Some(conn)));
+        }
+        Ok(Self(slot))
+    }
+}
+
+// Middleware finishing the transaction opened by `DatabaseTransaction`.
+// The body is buffered so the request can be replayed when the
+// transaction hits a serialization failure (40001).
+pub async fn transaction(
+    State(config): State<TransactionConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, *REQUEST_BODY_LIMIT).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+
+    let mut attempt = 0;
+    loop {
+        let slot = Slot::default();
+        let mut request =
+            Request::from_parts(parts.clone(), Body::from(body.clone()));
+        request.extensions_mut().insert(TransactionContext {
+            slot: slot.clone(),
+            isolation: config.isolation,
+        });
+
+        let response = next.clone().run(request).await;
+        let Some(open) = slot.lock().await.take() else {
+            // the handler never used the transaction
+            return response;
+        };
+
+        let mut retry = response
+            .extensions()
+            .get::<SerializationFailure>()
+            .is_some();
+        if response.status().is_success() || response.status().is_redirection()
+        {
+            match open.finish("COMMIT").await {
+                Ok(()) => return response,
+                Err(err)
+                    if err.code()
+                        == Some(&SqlState::T_R_SERIALIZATION_FAILURE) =>
+                {
+                    retry = true
+                }
+                Err(err) => return AppError::DBError(err).into_response(),
+            }
+        } else if open.finish("ROLLBACK").await.is_err() {
+            error!("Transaction - failed to roll back");
+        }
+
+        if !retry || attempt >= config.max_retries {
+            return response;
+        }
+        attempt += 1;
+    }
+}
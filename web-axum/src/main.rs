@@ -1,9 +1,13 @@
+mod api;
+mod audit;
 mod common;
 mod db;
+mod storage;
 mod users;
 
 use common::error::{internal_error, AppError};
 use common::extractor::JSONValidate;
+use common::utils::{MIGRATE_ON_STARTUP, REQUEST_BODY_LIMIT};
 use db::extractors::{ConnectionPool, DatabaseConnection};
 
 use axum::body::HttpBody;
@@ -15,23 +19,24 @@ use axum::{
     debug_handler,
     error_handling::HandleErrorLayer,
     extract::{
-        DefaultBodyLimit, FromRef, FromRequestParts, MatchedPath, Path,
-        Request, State,
+        DefaultBodyLimit, FromRef, FromRequestParts, MatchedPath, Request,
+        State,
     },
     http::{request::Parts, HeaderMap, HeaderName, Method, StatusCode, Uri},
     middleware::{self, Next},
     response::Response,
-    routing::{delete, get, post, put},
-    BoxError, Json, RequestPartsExt, Router,
+    routing::get,
+    BoxError, RequestPartsExt, Router,
 };
-use serde::{Deserialize, Serialize};
 use std::env;
 use std::fmt::{Display, Pointer};
+use std::net::SocketAddr;
 use std::string::String;
 use std::time::Duration;
 use tokio::time::sleep;
 use tower::ServiceBuilder;
 use tower_http::sensitive_headers::SetSensitiveHeadersLayer;
+use tower_http::services::ServeDir;
 use tower_http::trace::{self};
 use tower_http::{
     catch_panic::CatchPanicLayer,
@@ -40,41 +45,15 @@ use tower_http::{
     trace::{DefaultMakeSpan, DefaultOnRequest, TraceLayer},
 };
 use tracing::error;
-use tracing::{info, Level};
+use tracing::Level;
 use tracing::{info_span, Span};
 use tracing_subscriber::fmt::layer;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
-use validator::Validate;
 
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
 use tokio_postgres::NoTls;
 
-// the input to our `create_user` handler
-#[derive(Deserialize, Debug, Validate)]
-struct CreateUser {
-    id: u64,
-    #[validate(
-        length(min = 5, message = "exceed allowed min"),
-        length(max = 10, message = "exceed allowed max")
-    )]
-    username: String,
-    #[validate(
-        length(min = 3, message = "exceed allowed min"),
-        length(max = 7, message = "exceed allowed max")
-    )]
-    address: String,
-}
-
-// the output to our `create_user` handler
-#[derive(Validate, Debug, Serialize, Deserialize)]
-struct User<'a> {
-    id: u64,
-    #[validate(length(max = 1))]
-    username: &'a str,
-    address: &'a str,
-}
-
 async fn my_middleware(request: Request, next: Next) -> Response {
     // do something with `request`...
     println!("my middleware start");
@@ -166,23 +145,47 @@ async fn main() {
     .unwrap();
     let pool = Pool::builder().build(manager).await.unwrap();
 
-    let auth_routes =
-        Router::new().nest("/users", users::routes::auth_routes());
+    // `web_axum migrate up|down|status` manages the schema and exits
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        if let Err(err) =
+            db::migrate::run_command(&pool, args.get(2).map(String::as_str))
+                .await
+        {
+            eprintln!("migrate: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if *MIGRATE_ON_STARTUP {
+        match db::migrate::up(&pool).await {
+            Ok(applied) => {
+                for name in applied {
+                    tracing::info!("Applied migration {}", name);
+                }
+            }
+            Err(err) => panic!("Migration failed: {}", err),
+        }
+    }
+
+    tokio::spawn(users::tasks::purge_deleted_users_task(pool.clone()));
 
     // build our application with a route
     let app = Router::new()
         // `GET /` goes to `root`
         .route("/", get(root))
-        .nest("/api", auth_routes)
-        // `POST /users` goes to `create_user`
-        .route("/users", post(add_update_user))
-        .route("/users/:user_id", get(get_user))
-        .route("/users/:user_id", put(add_update_user))
-        .route("/users/:user_id/delete", delete(delete_user))
+        .nest("/api", api::routes())
+        // files written by the local blob store
+        .nest_service(
+            "/media",
+            ServeDir::new(
+                env::var("BLOB_LOCAL_ROOT").unwrap_or(String::from("media")),
+            ),
+        )
         .layer(
             ServiceBuilder::new()
-                // BODY LIMIT 100 KB
-                .layer(DefaultBodyLimit::max(100000))
+                // BODY LIMIT 100 KB by default
+                .layer(DefaultBodyLimit::max(*REQUEST_BODY_LIMIT))
                 // Should response within max 10 seconds
                 .layer(HandleErrorLayer::new(|_: BoxError| async {
                     StatusCode::REQUEST_TIMEOUT
@@ -202,7 +205,12 @@ async fn main() {
     tracing::debug!("De debug 12312");
     tracing::info!("De INFO 123");
     tracing::error!("Err logg");
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 // basic handler that responds with a static string
@@ -222,80 +230,3 @@ async fn root(
     // sleep(Duration::from_secs(2)).await;
     Ok((StatusCode::OK, String::from("Hello World")))
 }
-
-#[debug_handler]
-async fn add_update_user(
-    path: Option<Path<String>>,
-    //JSONValidate(payload): JSONValidate<CreateUser>,
-    Json(payload): Json<CreateUser>,
-) -> impl IntoResponse {
-    println!("User id {:?} {:?}", path, payload);
-    let user_id = match path {
-        Some(val) => val.0.parse::<u64>().unwrap_or(0),
-        None => payload.id,
-    };
-    if user_id == 0 {
-        // return StatusCode::BAD_REQUEST.into_response()
-        return AppError::UnexpectedError.into_response();
-    }
-
-    if payload.id == 1 {
-        return AppError::FatalError(format!(
-            "{} {:?}",
-            "Error Found, Please check", payload
-        ))
-        .into_response();
-        // panic!("Errr");
-    } else if payload.id == 2 {
-        return AppError::FatalError("invalid id".to_string()).into_response();
-    }
-
-    let address = format!("Address {}", payload.username);
-    let user = User {
-        id: user_id,
-        username: payload.username.as_str(),
-        address: address.as_str(),
-    };
-    info!("add update user {:?}", user);
-    let validate = user.validate();
-    println!("Validate {:?}", validate);
-    let data = r#"
-    {
-        "id": 43,
-        "username": "Joel",
-        "address": "+123 55555"
-    }"#;
-    let data = serde_json::from_str::<User>(data);
-    match data {
-        Ok(ref person) => {
-            println!("Deserialized person: {:?}", person);
-            let json_data = serde_json::to_string(&data.unwrap());
-            println!("JSON DATA - {:?}", json_data);
-        }
-        Err(error) => {
-            println!("Failed to deserialize data. Error: {}", error);
-        }
-    }
-    // this will be converted into a JSON response
-    // with a status code of `201 Created`
-    Json(user).into_response()
-}
-
-#[debug_handler]
-async fn get_user(Path(user_id): Path<String>) -> Response {
-    let username_id = user_id.clone() + " Hhaha";
-    let user = User {
-        id: user_id.parse::<u64>().unwrap(),
-        username: &username_id,
-        address: "Hello World",
-    };
-    Json(user).into_response()
-}
-
-async fn delete_user(Path(user_id): Path<String>) -> StatusCode {
-    let user_id = user_id;
-    if user_id != "1" {
-        return StatusCode::NO_CONTENT;
-    }
-    StatusCode::OK
-}
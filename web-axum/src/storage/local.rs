@@ -0,0 +1,67 @@
+use axum::async_trait;
+use axum::body::Bytes;
+use std::env;
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::error;
+
+use crate::common::error::{AppError, Result};
+use crate::storage::BlobStore;
+
+pub struct LocalStore {
+    pub root: PathBuf,
+    pub public_url: String,
+}
+
+impl LocalStore {
+    pub fn from_env() -> Self {
+        Self {
+            root: PathBuf::from(
+                env::var("BLOB_LOCAL_ROOT").unwrap_or("media".to_string()),
+            ),
+            public_url: env::var("BLOB_PUBLIC_URL")
+                .unwrap_or("/media".to_string()),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(AppError::FatalError("Invalid blob key".to_string()));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalStore {
+    async fn put(
+        &self,
+        key: &str,
+        _content_type: &str,
+        data: Bytes,
+    ) -> Result<String> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(|err| {
+                error!("Local store - create dir error {:?}", err);
+                AppError::FatalError("Failed to store file".to_string())
+            })?;
+        }
+        fs::write(&path, data).await.map_err(|err| {
+            error!("Local store - write error {:?}", err);
+            AppError::FatalError("Failed to store file".to_string())
+        })?;
+        Ok(format!("{}/{}", self.public_url.trim_end_matches('/'), key))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                error!("Local store - delete error {:?}", err);
+                Err(AppError::FatalError("Failed to delete file".to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+mod local;
+mod s3;
+
+use axum::async_trait;
+use axum::body::Bytes;
+use once_cell::sync::Lazy;
+use std::env;
+use std::sync::Arc;
+
+use crate::common::error::Result;
+
+pub use local::LocalStore;
+pub use s3::S3Store;
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    // store `data` under `key` and return its public url
+    async fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+    ) -> Result<String>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+// `BLOB_STORE=s3` selects the S3 compatible store, anything else stores
+// blobs on the local filesystem
+pub static BLOB_STORE: Lazy<Arc<dyn BlobStore>> =
+    Lazy::new(|| match env::var("BLOB_STORE").as_deref() {
+        Ok("s3") => Arc::new(S3Store::from_env()),
+        _ => Arc::new(LocalStore::from_env()),
+    });
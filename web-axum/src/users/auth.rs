@@ -0,0 +1,101 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts},
+};
+
+use crate::common::error::{internal_error, AppError};
+use crate::db::extractors::ConnectionPool;
+use crate::users::db::get_session_user;
+
+// Owner of the `Authorization: Bearer <token>` session
+pub struct SessionUser {
+    pub id: String,
+    pub is_admin: bool,
+}
+
+impl SessionUser {
+    // Allow `user_id` itself and admins
+    pub fn ensure_owner_or_admin(&self, user_id: &str) -> Result<(), AppError> {
+        if self.id != user_id && !self.is_admin {
+            return Err(AppError::Forbidden("Access denied".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionUser
+where
+    ConnectionPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| {
+                AppError::Unauthorized("Missing session token".to_string())
+            })?;
+
+        let pool = ConnectionPool::from_ref(state);
+        let conn = pool.get_owned().await.map_err(internal_error)?;
+        let (id, is_admin) =
+            get_session_user(&conn, token).await?.ok_or_else(|| {
+                AppError::Unauthorized("Invalid session token".to_string())
+            })?;
+        Ok(Self { id, is_admin })
+    }
+}
+
+// Id of the user owning the session
+pub struct AuthUser(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    ConnectionPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let user = SessionUser::from_request_parts(parts, state).await?;
+        Ok(Self(user.id))
+    }
+}
+
+// Id of the session owner, rejected unless they are an admin
+pub struct AdminUser(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminUser
+where
+    ConnectionPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let user = SessionUser::from_request_parts(parts, state).await?;
+        if !user.is_admin {
+            return Err(AppError::Forbidden(
+                "Admin access required".to_string(),
+            ));
+        }
+        Ok(Self(user.id))
+    }
+}
@@ -0,0 +1,181 @@
+use axum::body::Bytes;
+use image::{ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+use tracing::error;
+
+use crate::common::error::{AppError, Result};
+use crate::common::response::ErrorResponse;
+use crate::common::utils::uuid7_b62;
+use crate::storage::BLOB_STORE;
+
+static THUMBNAIL_SIZES: [u32; 3] = [64, 128, 256];
+// Decoding limits, a few KB of compressed pixels can expand to gigabytes
+static MAX_DIMENSION: u32 = 4096;
+static MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+// Sniff the uploaded bytes, resize them into thumbnails and store every
+// variant, returning the url of the original image
+pub async fn store_avatar(user_id: &str, data: Bytes) -> Result<String> {
+    let format = image::guess_format(&data).map_err(|_| {
+        AppError::from(ErrorResponse::create_error("Unsupported image format"))
+    })?;
+    let (extension, content_type) = match format {
+        ImageFormat::Png => ("png", "image/png"),
+        ImageFormat::Jpeg => ("jpg", "image/jpeg"),
+        ImageFormat::WebP => ("webp", "image/webp"),
+        ImageFormat::Gif => ("gif", "image/gif"),
+        _ => {
+            return Err(AppError::from(ErrorResponse::create_error(
+                "Unsupported image format",
+            )))
+        }
+    };
+
+    let source = data.clone();
+    let thumbnails =
+        tokio::task::spawn_blocking(move || resize_thumbnails(&source, format))
+            .await
+            .map_err(|_| AppError::UnexpectedError)??;
+
+    let prefix = format!("avatars/{}/{}", user_id, uuid7_b62());
+    let mut variants: Vec<(String, &str, Bytes)> = thumbnails
+        .into_iter()
+        .map(|(size, thumbnail)| {
+            (
+                format!("{}/{}.png", prefix, size),
+                "image/png",
+                Bytes::from(thumbnail),
+            )
+        })
+        .collect();
+    variants.push((
+        format!("{}/original.{}", prefix, extension),
+        content_type,
+        data,
+    ));
+
+    let mut stored = Vec::new();
+    let mut url = String::new();
+    for (key, content_type, data) in variants {
+        match BLOB_STORE.put(&key, content_type, data).await {
+            Ok(value) => {
+                url = value;
+                stored.push(key);
+            }
+            Err(err) => {
+                // don't leave orphaned variants behind
+                for key in stored {
+                    if BLOB_STORE.delete(&key).await.is_err() {
+                        error!("Avatar - failed to remove orphaned {}", key);
+                    }
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(url)
+}
+
+fn resize_thumbnails(
+    data: &[u8],
+    format: ImageFormat,
+) -> Result<Vec<(u32, Vec<u8>)>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|_| {
+        AppError::from(ErrorResponse::create_error("Invalid image file"))
+    })?;
+
+    THUMBNAIL_SIZES
+        .iter()
+        .map(|size| {
+            let mut buffer = Cursor::new(Vec::new());
+            image
+                .thumbnail(*size, *size)
+                .write_to(&mut buffer, ImageFormat::Png)
+                .map_err(|_| {
+                    AppError::FatalError("Failed to resize image".to_string())
+                })?;
+            Ok((*size, buffer.into_inner()))
+        })
+        .collect()
+}
+
+// Blob key of the avatar `url` of `user_id`, keys outside the user's own
+// `avatars/<user_id>/` prefix are refused
+fn avatar_key<'a>(user_id: &str, url: &'a str) -> Result<Option<&'a str>> {
+    let Some(idx) = url.find("avatars/") else {
+        return Ok(None);
+    };
+    let key = &url[idx..];
+    let owned = key
+        .strip_prefix(&format!("avatars/{}/", user_id))
+        .is_some_and(|rest| !rest.split('/').any(|part| part == ".."));
+    if !owned {
+        return Err(AppError::FatalError("Invalid avatar key".to_string()));
+    }
+    Ok(Some(key))
+}
+
+// Remove every variant stored next to the original image `url`
+pub async fn remove_avatar(user_id: &str, url: &str) -> Result<()> {
+    let Some(original) = avatar_key(user_id, url)? else {
+        return Ok(());
+    };
+    let Some((prefix, _)) = original.rsplit_once('/') else {
+        return Ok(());
+    };
+    for size in THUMBNAIL_SIZES {
+        BLOB_STORE
+            .delete(&format!("{}/{}.png", prefix, size))
+            .await?;
+    }
+    BLOB_STORE.delete(original).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageFormat};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        GrayImage::new(width, height)
+            .write_to(&mut buffer, ImageFormat::Png)
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn avatar_key_stays_in_user_prefix() {
+        let url = "https://cdn.example.com/avatars/u1/abc/original.png";
+        assert_eq!(
+            avatar_key("u1", url).ok().unwrap(),
+            Some("avatars/u1/abc/original.png")
+        );
+        assert_eq!(avatar_key("u1", "https://x.com/a.png").ok().unwrap(), None);
+        assert!(avatar_key("u2", url).is_err());
+        assert!(avatar_key("u1", "/media/avatars/u10/a/original.png").is_err());
+        assert!(avatar_key("u1", "avatars/u1/../u2/a/original.png").is_err());
+    }
+
+    #[test]
+    fn resize_builds_every_thumbnail() {
+        let thumbnails = resize_thumbnails(&png(300, 200), ImageFormat::Png)
+            .ok()
+            .unwrap();
+        let sizes: Vec<u32> =
+            thumbnails.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, THUMBNAIL_SIZES);
+    }
+
+    #[test]
+    fn resize_rejects_oversized_images() {
+        let data = png(MAX_DIMENSION + 1, 1);
+        assert!(resize_thumbnails(&data, ImageFormat::Png).is_err());
+    }
+}
@@ -1,15 +1,41 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::string::ToString;
 
+use crate::audit::record_event;
 use crate::common::error::{AppError, Result};
 use crate::common::response::ErrorResponse;
 use crate::common::utils::{
-    uuid7_b62, Password::generate_password_hash, PASSWORD_ITERATION,
+    session_token, uuid7_b62, Password::generate_password_hash,
+    DATA_EXPORT_TTL_DAYS, ERASURE_COOLING_OFF_DAYS, PASSWORD_ITERATION,
+    SESSION_TTL, USERNAME_ALIAS_DAYS, USERNAME_CHANGE_COOLDOWN_DAYS,
 };
 use crate::db::extractors::ConnectionPooled;
-use crate::users::models::User;
+use crate::db::query::InsertBuilder;
+use crate::users::models::{NewUser, User, UserCreated, UserInserted};
+use crate::users::schema::{UserEdit, UserSettings};
+use serde_json::{json, Value};
+use tokio_postgres::GenericClient;
+use tokio_postgres::Row;
+
+// `<first name><id suffix>` following the `UsernameChange` rules, at most
+// 30 characters matching `USERNAME_FORMAT`
+pub fn generate_username(first_name: &str, user_id: &str) -> String {
+    let prefix: String = first_name
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+        .collect();
+    let prefix = prefix.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    let prefix = match &prefix[..prefix.len().min(21)] {
+        "" => "user",
+        prefix => prefix.trim_end_matches(['_', '.']),
+    };
+    prefix.to_string() + &user_id[user_id.len() - 9..]
+}
 
 pub async fn create_user<'a>(
     con: ConnectionPooled,
@@ -18,22 +44,6 @@ pub async fn create_user<'a>(
     first_name: Option<&'a str>,
     last_name: Option<&'a str>,
 ) -> Result<User<'a>> {
-    let user_created: bool = con
-        .query_one(
-            "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)",
-            &[&email],
-        )
-        .await
-        .map_err(|_| AppError::FatalError("Unable to create user".to_string()))?
-        .get(0);
-
-    if user_created {
-        return Err(AppError::from(ErrorResponse::create_error(
-            "Email already exists",
-        )));
-    }
-
-    let now = Utc::now();
     let user_id = uuid7_b62();
 
     let email_prefix = email.split("@").next().unwrap();
@@ -42,8 +52,7 @@ pub async fn create_user<'a>(
         _ => email_prefix,
     };
 
-    let username = first_name.unwrap_or_else(|| email_prefix).to_lowercase()
-        + &user_id[user_id.len() - 9..];
+    let username = generate_username(user_first_name, &user_id);
 
     let user_password_hash = match password {
         Some(password) => generate_password_hash(
@@ -57,34 +66,718 @@ pub async fn create_user<'a>(
         _ => "".to_string(),
     };
 
+    let new_user = NewUser {
+        id: user_id,
+        email: email.to_string(),
+        username,
+        first_name: user_first_name.to_string(),
+        last_name: last_name.map(str::to_string),
+        password: user_password_hash,
+    };
+
+    // duplicated emails are rejected by the `users_email_key` unique index
+    // and reported as a field error, see `AppError::DBError`
+    let created: UserCreated =
+        InsertBuilder::new(std::slice::from_ref(&new_user))
+            .returning("id, create_at")
+            .fetch_one(&*con)
+            .await?;
+
     let user = User {
-        id: Some(Cow::Owned(user_id)),
+        id: Some(Cow::Owned(created.id)),
         email: Some(email),
         image: None,
-        username: Some(Cow::Owned(username)),
+        username: Some(Cow::Owned(new_user.username)),
         first_name: Some(user_first_name),
         last_name,
-        create_at: Some(now),
-        is_active: None,
+        create_at: Some(created.create_at),
+        is_active: Some(true),
         update_at: None,
         last_login: None,
+        login_count: None,
+        last_login_ip: None,
+        deleted_at: None,
     };
 
-    let query = "INSERT INTO users (\
-    id, email, username, first_name, last_name, password) \
-    VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, create_at";
-    con.query_one(
-        query,
-        &[
-            user.id.as_ref().unwrap(),
-            &email,
-            user.username.as_ref().unwrap(),
-            &user_first_name,
-            &last_name,
-            &user_password_hash,
-        ],
+    Ok(user)
+}
+
+pub async fn users_due_for_purge(
+    con: &ConnectionPooled,
+    retention_days: i32,
+) -> Result<Vec<String>> {
+    let rows = con
+        .query(
+            "SELECT id FROM users WHERE deleted_at IS NOT NULL \
+            AND deleted_at < now() - make_interval(days => $1)",
+            &[&retention_days],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Hard delete a user still due for purge. Data exports cascade with the
+// user, so their blob keys are returned along with the image to be removed
+// once committed, `None` when the user was restored in the meantime.
+pub async fn purge_user(
+    con: &mut ConnectionPooled,
+    user_id: &str,
+    retention_days: i32,
+) -> Result<Option<(Option<String>, Vec<String>)>> {
+    let transaction = con.transaction().await?;
+    let Some(row) = transaction
+        .query_opt(
+            "SELECT image FROM users WHERE id = $1 \
+            AND deleted_at < now() - make_interval(days => $2) FOR UPDATE",
+            &[&user_id, &retention_days],
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let image: Option<String> = row.get(0);
+
+    let exports = transaction
+        .query(
+            "SELECT blob_key FROM user_data_exports WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    transaction
+        .execute("DELETE FROM users WHERE id = $1", &[&user_id])
+        .await?;
+    transaction.commit().await?;
+
+    Ok(Some((
+        image,
+        exports.iter().map(|row| row.get(0)).collect(),
+    )))
+}
+
+pub async fn purge_expired_username_aliases(
+    con: &ConnectionPooled,
+) -> Result<u64> {
+    let purged = con
+        .execute("DELETE FROM username_aliases WHERE expire_at < now()", &[])
+        .await?;
+    Ok(purged)
+}
+
+pub async fn set_user_active<C: GenericClient>(
+    con: &C,
+    user_id: &str,
+    is_active: bool,
+    reason: Option<&str>,
+) -> Result<Option<Row>> {
+    let row = con
+        .query_opt(
+            "UPDATE users SET is_active = $1, inactive_reason = $2, \
+            version = version + 1, \
+            active_at = CASE WHEN $1 THEN now() ELSE active_at END \
+            WHERE id = $3 AND deleted_at IS NULL RETURNING id, email, image, \
+            username, first_name, last_name, is_active, create_at, \
+            update_at, last_login",
+            &[&is_active, &reason, &user_id],
+        )
+        .await?;
+    Ok(row)
+}
+
+pub async fn get_user(con: &ConnectionPooled, user_id: &str) -> Result<Row> {
+    con.query_opt(
+        "SELECT id, email, image, username, first_name, last_name, \
+        is_active, create_at, update_at, last_login, version, login_count, \
+        host(last_login_ip) AS last_login_ip FROM users WHERE id = $1 \
+        AND deleted_at IS NULL",
+        &[&user_id],
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+pub async fn record_login(
+    con: &ConnectionPooled,
+    user_id: &str,
+    ip: IpAddr,
+) -> Result<Row> {
+    let row = con
+        .query_one(
+            "UPDATE users SET last_login = now(), \
+            login_count = login_count + 1, last_login_ip = $2, \
+            version = version + 1 WHERE id = $1 \
+            RETURNING last_login, login_count, host(last_login_ip)",
+            &[&user_id, &ip],
+        )
+        .await?;
+    Ok(row)
+}
+
+pub async fn create_session(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<String> {
+    let token = session_token();
+    con.execute(
+        "INSERT INTO user_sessions (id, user_id, expire_at) \
+        VALUES ($1, $2, now() + make_interval(secs => $3))",
+        &[&token, &user_id, &(*SESSION_TTL as f64)],
     )
     .await?;
+    Ok(token)
+}
 
-    Ok(user)
+pub async fn get_session_user(
+    con: &ConnectionPooled,
+    token: &str,
+) -> Result<Option<(String, bool)>> {
+    let row = con
+        .query_opt(
+            "SELECT s.user_id, u.is_admin FROM user_sessions s \
+            JOIN users u ON u.id = s.user_id \
+            WHERE s.id = $1 AND s.expire_at > now() \
+            AND u.deleted_at IS NULL AND u.is_active",
+            &[&token],
+        )
+        .await?;
+    Ok(row.map(|row| (row.get(0), row.get(1))))
+}
+
+pub async fn revoke_user_sessions<C: GenericClient>(
+    con: &C,
+    user_id: &str,
+) -> Result<u64> {
+    let revoked = con
+        .execute("DELETE FROM user_sessions WHERE user_id = $1", &[&user_id])
+        .await?;
+    Ok(revoked)
+}
+
+// Update only when the row is still at `version`, otherwise the user was
+// modified concurrently and the precondition fails
+pub async fn update_user(
+    con: &ConnectionPooled,
+    user_id: &str,
+    version: i32,
+    user: &UserEdit,
+) -> Result<Row> {
+    con.query_opt(
+        "UPDATE users SET first_name = $1, last_name = $2, \
+        version = version + 1 WHERE id = $3 AND version = $4 \
+        AND deleted_at IS NULL RETURNING id, email, image, username, \
+        first_name, last_name, is_active, create_at, update_at, last_login, \
+        version",
+        &[&user.first_name, &user.last_name, &user_id, &version],
+    )
+    .await?
+    .ok_or(AppError::PreconditionFailed)
+}
+
+// Change the username of `user_id` keeping the previous one as an alias
+pub async fn change_username(
+    con: &mut ConnectionPooled,
+    user_id: &str,
+    username: &str,
+) -> Result<Row> {
+    let transaction = con.transaction().await?;
+
+    let is_aliased: bool = transaction
+        .query_one(
+            "SELECT EXISTS(SELECT 1 FROM username_aliases \
+            WHERE username = lower($1) AND user_id <> $2 \
+            AND expire_at > now())",
+            &[&username, &user_id],
+        )
+        .await?
+        .get(0);
+    if is_aliased {
+        return Err(AppError::from(ErrorResponse {
+            errors: Some(HashMap::from([(
+                "username".to_string(),
+                Cow::Borrowed("already exists"),
+            )])),
+            error: None,
+        }));
+    }
+
+    let previous = transaction
+        .query_opt(
+            "SELECT username FROM users WHERE id = $1 \
+            AND deleted_at IS NULL AND (username_changed_at IS NULL \
+            OR username_changed_at < now() - make_interval(days => $2)) \
+            FOR UPDATE",
+            &[&user_id, &*USERNAME_CHANGE_COOLDOWN_DAYS],
+        )
+        .await?
+        .ok_or_else(|| {
+            AppError::from(ErrorResponse::create_error(
+                "Username was changed recently",
+            ))
+        })?;
+    let previous: &str = previous.get(0);
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET username = $1, username_changed_at = now(), \
+            version = version + 1 WHERE id = $2 RETURNING id, email, image, \
+            username, first_name, last_name, is_active, create_at, \
+            update_at, last_login, version",
+            &[&username, &user_id],
+        )
+        .await?;
+
+    transaction
+        .execute(
+            "DELETE FROM username_aliases WHERE username = lower($1)",
+            &[&username],
+        )
+        .await?;
+    if previous.to_lowercase() != username.to_lowercase() {
+        transaction
+            .execute(
+                "INSERT INTO username_aliases (username, user_id, expire_at) \
+                VALUES (lower($1), $2, now() + make_interval(days => $3)) \
+                ON CONFLICT (username) DO UPDATE \
+                SET user_id = $2, expire_at = EXCLUDED.expire_at",
+                &[&previous, &user_id, &*USERNAME_ALIAS_DAYS],
+            )
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(row)
+}
+
+pub async fn get_user_by_username(
+    con: &ConnectionPooled,
+    username: &str,
+) -> Result<Option<Row>> {
+    let row = con
+        .query_opt(
+            "SELECT id, email, image, username, first_name, last_name, \
+            is_active, create_at, update_at, last_login, version FROM users \
+            WHERE lower(username) = lower($1) AND deleted_at IS NULL",
+            &[&username],
+        )
+        .await?;
+    Ok(row)
+}
+
+// Current username of the user still owning `alias`
+pub async fn resolve_username_alias(
+    con: &ConnectionPooled,
+    alias: &str,
+) -> Result<Option<String>> {
+    let row = con
+        .query_opt(
+            "SELECT u.username FROM username_aliases a \
+            JOIN users u ON u.id = a.user_id \
+            WHERE a.username = lower($1) AND a.expire_at > now() \
+            AND u.deleted_at IS NULL",
+            &[&alias],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+// Id of the account `user_id` was merged into, if any
+pub async fn resolve_user_tombstone(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<Option<String>> {
+    let row = con
+        .query_opt(
+            "SELECT t.merged_into FROM user_tombstones t \
+            JOIN users u ON u.id = t.merged_into \
+            WHERE t.user_id = $1 AND u.deleted_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+// Move everything owned by `source_id` to `target_id`, then replace the
+// source account by a tombstone pointing to the target. The target keeps
+// its own profile and settings.
+pub async fn merge_users(
+    con: &mut ConnectionPooled,
+    target_id: &str,
+    source_id: &str,
+    actor_id: &str,
+) -> Result<Row> {
+    let transaction = con.transaction().await?;
+
+    let rows = transaction
+        .query(
+            "SELECT id, username, login_count, last_login FROM users \
+            WHERE id = ANY($1) AND deleted_at IS NULL AND erased_at IS NULL \
+            ORDER BY id FOR UPDATE",
+            &[&[target_id, source_id].as_slice()],
+        )
+        .await?;
+    let Some(source) =
+        rows.iter().find(|row| row.get::<_, &str>(0) == source_id)
+    else {
+        return Err(AppError::NotFound("User not found".to_string()));
+    };
+    if !rows.iter().any(|row| row.get::<_, &str>(0) == target_id) {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+    let source_username: &str = source.get(1);
+    let source_login_count: i32 = source.get(2);
+    let source_last_login: Option<DateTime<Utc>> = source.get(3);
+
+    // the source's sessions now sign in as the target and its audit trail
+    // follows, the merge event keeps the source id
+    for query in [
+        "UPDATE user_sessions SET user_id = $1 WHERE user_id = $2",
+        "UPDATE audit_events SET user_id = $1 WHERE user_id = $2",
+        "UPDATE audit_events SET actor_id = $1 WHERE actor_id = $2",
+        "INSERT INTO user_settings (user_id, settings) \
+        SELECT $1, settings FROM user_settings WHERE user_id = $2 \
+        ON CONFLICT (user_id) DO NOTHING",
+        "UPDATE username_aliases SET user_id = $1 WHERE user_id = $2",
+        "UPDATE user_data_exports SET user_id = $1 WHERE user_id = $2",
+        "UPDATE user_tombstones SET merged_into = $1 WHERE merged_into = $2",
+    ] {
+        transaction
+            .execute(query, &[&target_id, &source_id])
+            .await?;
+    }
+
+    transaction
+        .execute("DELETE FROM users WHERE id = $1", &[&source_id])
+        .await?;
+    transaction
+        .execute(
+            "INSERT INTO user_tombstones (user_id, merged_into) \
+            VALUES ($1, $2)",
+            &[&source_id, &target_id],
+        )
+        .await?;
+    transaction
+        .execute(
+            "INSERT INTO username_aliases (username, user_id, expire_at) \
+            VALUES (lower($1), $2, now() + make_interval(days => $3)) \
+            ON CONFLICT (username) DO UPDATE \
+            SET user_id = $2, expire_at = EXCLUDED.expire_at",
+            &[&source_username, &target_id, &*USERNAME_ALIAS_DAYS],
+        )
+        .await?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET login_count = login_count + $2, \
+            last_login = greatest(last_login, $3), version = version + 1 \
+            WHERE id = $1 RETURNING id, email, image, username, first_name, \
+            last_name, is_active, create_at, update_at, last_login, version, \
+            login_count, host(last_login_ip) AS last_login_ip",
+            &[&target_id, &source_login_count, &source_last_login],
+        )
+        .await?;
+
+    record_event(
+        &transaction,
+        Some(target_id),
+        Some(actor_id),
+        "user.merge",
+        json!({ "source_id": source_id }),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(row)
+}
+
+pub async fn get_user_settings(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<UserSettings> {
+    let row = con
+        .query_opt(
+            "SELECT settings FROM user_settings WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    match row {
+        Some(row) => serde_json::from_value(row.get(0))
+            .map_err(|_| AppError::FatalError("Invalid settings".to_string())),
+        None => Ok(UserSettings::default()),
+    }
+}
+
+// Settings of `user_id` with their row locked until the end of the
+// transaction, so concurrent patches apply one after the other
+pub async fn lock_user_settings<C: GenericClient>(
+    con: &C,
+    user_id: &str,
+) -> Result<UserSettings> {
+    con.execute(
+        "INSERT INTO user_settings (user_id) VALUES ($1) \
+            ON CONFLICT (user_id) DO NOTHING",
+        &[&user_id],
+    )
+    .await?;
+    let row = con
+        .query_one(
+            "SELECT settings FROM user_settings WHERE user_id = $1 \
+            FOR UPDATE",
+            &[&user_id],
+        )
+        .await?;
+    serde_json::from_value(row.get(0))
+        .map_err(|_| AppError::FatalError("Invalid settings".to_string()))
+}
+
+pub async fn save_user_settings<C: GenericClient>(
+    con: &C,
+    user_id: &str,
+    settings: &UserSettings,
+) -> Result<()> {
+    let settings = serde_json::to_value(settings)
+        .map_err(|_| AppError::UnexpectedError)?;
+    con.execute(
+        "INSERT INTO user_settings (user_id, settings) VALUES ($1, $2) \
+        ON CONFLICT (user_id) DO UPDATE \
+        SET settings = EXCLUDED.settings, update_at = now()",
+        &[&user_id, &settings],
+    )
+    .await?;
+    Ok(())
+}
+
+// Lowercased emails out of `emails` already taken by a user
+pub async fn existing_emails(
+    con: &ConnectionPooled,
+    emails: &[String],
+) -> Result<Vec<String>> {
+    let rows = con
+        .query(
+            "SELECT lower(email) FROM users WHERE lower(email) = ANY($1)",
+            &[&emails],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Multi-row INSERT, rows conflicting with an existing email are skipped
+// and missing from the returned emails
+pub async fn insert_users<C: GenericClient>(
+    con: &C,
+    users: &[NewUser],
+) -> Result<Vec<UserInserted>> {
+    if users.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    InsertBuilder::new(users)
+        .on_conflict("(lower(email))", "DO NOTHING")
+        .returning("lower(email) AS email")
+        .fetch(con)
+        .await
+}
+
+// Every record held about a user as JSON documents, keyed by file name.
+// Password hashes and session tokens are left out.
+pub async fn get_user_data(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<Vec<(&'static str, Value)>> {
+    let row = con
+        .query_one(
+            "SELECT \
+            (SELECT to_jsonb(u) - 'password' FROM users u WHERE u.id = $1), \
+            (SELECT coalesce(jsonb_agg(to_jsonb(s) - 'id' \
+                ORDER BY s.create_at), '[]'::jsonb) \
+                FROM user_sessions s WHERE s.user_id = $1), \
+            (SELECT coalesce(jsonb_agg(to_jsonb(a) ORDER BY a.create_at), \
+                '[]'::jsonb) FROM audit_events a WHERE a.user_id = $1), \
+            (SELECT coalesce(jsonb_agg(to_jsonb(n)), '[]'::jsonb) \
+                FROM username_aliases n WHERE n.user_id = $1)",
+            &[&user_id],
+        )
+        .await?;
+    let profile: Option<Value> = row.get(0);
+    let profile =
+        profile.ok_or(AppError::NotFound("User not found".to_string()))?;
+    Ok(vec![
+        ("profile.json", profile),
+        ("sessions.json", row.get(1)),
+        ("audit_events.json", row.get(2)),
+        ("username_aliases.json", row.get(3)),
+    ])
+}
+
+pub async fn create_data_export(
+    con: &ConnectionPooled,
+    user_id: &str,
+    blob_key: &str,
+    url: &str,
+) -> Result<DateTime<Utc>> {
+    let row = con
+        .query_one(
+            "INSERT INTO user_data_exports \
+            (id, user_id, blob_key, url, expire_at) \
+            VALUES ($1, $2, $3, $4, now() + make_interval(days => $5)) \
+            RETURNING expire_at",
+            &[
+                &uuid7_b62(),
+                &user_id,
+                &blob_key,
+                &url,
+                &*DATA_EXPORT_TTL_DAYS,
+            ],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+// Forget expired archives, returning their blob keys for removal
+pub async fn purge_expired_data_exports(
+    con: &ConnectionPooled,
+) -> Result<Vec<String>> {
+    let rows = con
+        .query(
+            "DELETE FROM user_data_exports WHERE expire_at < now() \
+            RETURNING blob_key",
+            &[],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Schedule the erasure of an account, a pending request is kept as is so
+// repeating it doesn't push the date back
+pub async fn request_erasure(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<DateTime<Utc>> {
+    let row = con
+        .query_opt(
+            "UPDATE users \
+            SET erasure_requested_at = coalesce(erasure_requested_at, now()) \
+            WHERE id = $1 AND erased_at IS NULL \
+            RETURNING erasure_requested_at + make_interval(days => $2)",
+            &[&user_id, &*ERASURE_COOLING_OFF_DAYS],
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+    Ok(row.get(0))
+}
+
+pub async fn cancel_erasure(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<bool> {
+    let updated = con
+        .execute(
+            "UPDATE users SET erasure_requested_at = NULL \
+            WHERE id = $1 AND erasure_requested_at IS NOT NULL \
+            AND erased_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    Ok(updated > 0)
+}
+
+pub async fn users_due_for_erasure(
+    con: &ConnectionPooled,
+) -> Result<Vec<String>> {
+    let rows = con
+        .query(
+            "SELECT id FROM users WHERE erased_at IS NULL \
+            AND erasure_requested_at < now() - make_interval(days => $1)",
+            &[&*ERASURE_COOLING_OFF_DAYS],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Anonymize the account in place so rows referencing the id stay valid.
+// Audit events are kept, they only reference the id. Returns the blob keys
+// and urls that belonged to the user, to be removed once committed.
+pub async fn erase_user(
+    con: &mut ConnectionPooled,
+    user_id: &str,
+) -> Result<(Option<String>, Vec<String>)> {
+    let transaction = con.transaction().await?;
+    let row = transaction
+        .query_opt(
+            "SELECT image FROM users WHERE id = $1 AND erased_at IS NULL \
+            FOR UPDATE",
+            &[&user_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+    let image: Option<String> = row.get(0);
+
+    transaction
+        .execute(
+            "UPDATE users SET email = id || '@erased.invalid', \
+            username = 'erased_' || id, first_name = NULL, last_name = NULL, \
+            image = NULL, password = '', is_active = false, \
+            inactive_reason = NULL, last_login_ip = NULL, \
+            erasure_requested_at = NULL, erased_at = now(), \
+            version = version + 1 WHERE id = $1",
+            &[&user_id],
+        )
+        .await?;
+    for table in ["user_sessions", "user_settings", "username_aliases"] {
+        transaction
+            .execute(
+                &format!("DELETE FROM {} WHERE user_id = $1", table),
+                &[&user_id],
+            )
+            .await?;
+    }
+    let exports = transaction
+        .query(
+            "DELETE FROM user_data_exports WHERE user_id = $1 \
+            RETURNING blob_key",
+            &[&user_id],
+        )
+        .await?;
+    // audit trail entries keep the action but not the personal data
+    transaction
+        .execute(
+            "UPDATE audit_events SET data = '{}' WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    record_event(
+        &transaction,
+        Some(user_id),
+        None,
+        "privacy.erased",
+        json!({}),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok((image, exports.iter().map(|row| row.get(0)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils::USERNAME_FORMAT;
+
+    #[test]
+    fn generated_usernames_are_valid() {
+        let user_id = "0MzHqXn2Wr1Yb4TfKc7Ld";
+        for (first_name, expected) in [
+            ("Ana", "anab4TfKc7Ld"),
+            ("José María", "josmarab4TfKc7Ld"),
+            ("_o'neil.", "oneilb4TfKc7Ld"),
+            ("", "userb4TfKc7Ld"),
+            ("李", "userb4TfKc7Ld"),
+            (
+                "abcdefghij.klmnopqrs_tuvwxyz",
+                "abcdefghij.klmnopqrsb4TfKc7Ld",
+            ),
+        ] {
+            let username = generate_username(first_name, user_id);
+            assert_eq!(username, expected);
+            assert!(username.len() <= 30);
+            assert!(USERNAME_FORMAT.is_match(&username));
+        }
+    }
 }
@@ -0,0 +1,257 @@
+use arrow_array::{
+    ArrayRef, BooleanArray, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use axum::body::{Body, Bytes};
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_postgres::Row;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::error;
+
+use crate::common::error::{AppError, Result};
+use crate::common::to_sql::{as_sql_params, SqlParam};
+use crate::common::utils::EXPORT_BATCH_SIZE;
+use crate::db::extractors::ConnectionPooled;
+
+// `deleted_at` is always exported, it tells soft deleted users apart when
+// they are included
+pub static EXPORT_COLUMNS: &str = "id, email, username, first_name, \
+    last_name, is_active, create_at, update_at, last_login, deleted_at";
+
+#[derive(Debug, Default, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    id: &'a str,
+    email: &'a str,
+    username: &'a str,
+    first_name: Option<&'a str>,
+    last_name: Option<&'a str>,
+    is_active: Option<bool>,
+    create_at: DateTime<Utc>,
+    update_at: Option<DateTime<Utc>>,
+    last_login: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a Row> for ExportRow<'a> {
+    fn from(row: &'a Row) -> Self {
+        Self {
+            id: row.get(0),
+            email: row.get(1),
+            username: row.get(2),
+            first_name: row.get(3),
+            last_name: row.get(4),
+            is_active: row.get(5),
+            create_at: row.get(6),
+            update_at: row.get(7),
+            last_login: row.get(8),
+            deleted_at: row.get(9),
+        }
+    }
+}
+
+enum Encoder {
+    Csv { has_header: bool },
+    Ndjson,
+    Parquet(Box<ArrowWriter<Vec<u8>>>, SchemaRef),
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self> {
+        Ok(match format {
+            ExportFormat::Csv => Self::Csv { has_header: true },
+            ExportFormat::Ndjson => Self::Ndjson,
+            ExportFormat::Parquet => {
+                let timestamp = DataType::Timestamp(
+                    TimeUnit::Microsecond,
+                    Some("UTC".into()),
+                );
+                let schema = Arc::new(Schema::new(vec![
+                    Field::new("id", DataType::Utf8, false),
+                    Field::new("email", DataType::Utf8, false),
+                    Field::new("username", DataType::Utf8, false),
+                    Field::new("first_name", DataType::Utf8, true),
+                    Field::new("last_name", DataType::Utf8, true),
+                    Field::new("is_active", DataType::Boolean, true),
+                    Field::new("create_at", timestamp.clone(), false),
+                    Field::new("update_at", timestamp.clone(), true),
+                    Field::new("last_login", timestamp.clone(), true),
+                    Field::new("deleted_at", timestamp, true),
+                ]));
+                let writer =
+                    ArrowWriter::try_new(Vec::new(), schema.clone(), None)
+                        .map_err(|_| AppError::UnexpectedError)?;
+                Self::Parquet(Box::new(writer), schema)
+            }
+        })
+    }
+
+    // Encode a batch of rows, returning the bytes ready to be sent
+    fn encode(&mut self, rows: &[Row]) -> Result<Vec<u8>> {
+        match self {
+            Self::Csv { has_header } => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(*has_header)
+                    .from_writer(Vec::new());
+                *has_header = false;
+                for row in rows {
+                    writer
+                        .serialize(ExportRow::from(row))
+                        .map_err(|_| AppError::UnexpectedError)?;
+                }
+                writer.into_inner().map_err(|_| AppError::UnexpectedError)
+            }
+            Self::Ndjson => {
+                let mut buffer = Vec::new();
+                for row in rows {
+                    serde_json::to_writer(&mut buffer, &ExportRow::from(row))
+                        .map_err(|_| AppError::UnexpectedError)?;
+                    buffer.push(b'\n');
+                }
+                Ok(buffer)
+            }
+            Self::Parquet(writer, schema) => {
+                let batch = record_batch(schema.clone(), rows)?;
+                writer
+                    .write(&batch)
+                    .map_err(|_| AppError::UnexpectedError)?;
+                // every batch becomes a row group so its bytes can be sent
+                writer.flush().map_err(|_| AppError::UnexpectedError)?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            Self::Parquet(writer, _) => {
+                writer.into_inner().map_err(|_| AppError::UnexpectedError)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+fn record_batch(schema: SchemaRef, rows: &[Row]) -> Result<RecordBatch> {
+    let rows: Vec<ExportRow> = rows.iter().map(ExportRow::from).collect();
+    let timestamps = |values: Vec<Option<DateTime<Utc>>>| -> ArrayRef {
+        Arc::new(
+            TimestampMicrosecondArray::from(
+                values
+                    .into_iter()
+                    .map(|value| value.map(|value| value.timestamp_micros()))
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC"),
+        )
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.id))),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.email),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.username),
+        )),
+        Arc::new(StringArray::from(
+            rows.iter().map(|row| row.first_name).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            rows.iter().map(|row| row.last_name).collect::<Vec<_>>(),
+        )),
+        Arc::new(BooleanArray::from(
+            rows.iter().map(|row| row.is_active).collect::<Vec<_>>(),
+        )),
+        timestamps(rows.iter().map(|row| Some(row.create_at)).collect()),
+        timestamps(rows.iter().map(|row| row.update_at).collect()),
+        timestamps(rows.iter().map(|row| row.last_login).collect()),
+        timestamps(rows.iter().map(|row| row.deleted_at).collect()),
+    ];
+    RecordBatch::try_new(schema, columns).map_err(|_| AppError::UnexpectedError)
+}
+
+// Stream every row matching `query` through a server side cursor, only
+// one batch of rows is held in memory at a time
+pub fn export_users(
+    conn: ConnectionPooled,
+    query: String,
+    params: Vec<SqlParam>,
+    format: ExportFormat,
+) -> Body {
+    let (sender, receiver) = channel(4);
+    tokio::spawn(async move {
+        if stream_rows(conn, &query, &params, format, &sender)
+            .await
+            .is_err()
+        {
+            error!("Export users - failed to stream rows");
+            let _ = sender.send(Err(io::Error::other("export failed"))).await;
+        }
+    });
+    Body::from_stream(ReceiverStream::new(receiver))
+}
+
+async fn stream_rows(
+    mut conn: ConnectionPooled,
+    query: &str,
+    params: &[SqlParam],
+    format: ExportFormat,
+    sender: &Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    let transaction = conn.transaction().await?;
+    let portal = transaction.bind(query, &as_sql_params(params)).await?;
+    let mut encoder = Encoder::new(format)?;
+
+    loop {
+        let rows = transaction
+            .query_portal(&portal, *EXPORT_BATCH_SIZE)
+            .await?;
+        if rows.is_empty() {
+            break;
+        }
+        let chunk = encoder.encode(&rows)?;
+        if sender.send(Ok(Bytes::from(chunk))).await.is_err() {
+            // client went away
+            return Ok(());
+        }
+    }
+
+    let chunk = encoder.finish()?;
+    if !chunk.is_empty() {
+        let _ = sender.send(Ok(Bytes::from(chunk))).await;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use validator::Validate;
+
+use crate::common::response::ErrorResponse;
+use crate::common::utils::{
+    uuid7_b62, Password::generate_password_hash, PASSWORD_ITERATION,
+};
+use crate::users::db::generate_username;
+use crate::users::models::NewUser;
+use crate::users::schema::UserImportRow;
+
+// Validation errors keyed by `<row>.<field>`
+pub type RowErrors = HashMap<String, Cow<'static, str>>;
+
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ImportFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next().unwrap_or_default().trim() {
+            "text/csv" => Some(Self::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+// Parse and validate every row, errors are keyed by `<row>.<field>` with
+// rows numbered from 1 not counting the CSV header
+pub fn parse_rows(
+    format: ImportFormat,
+    body: &[u8],
+) -> (Vec<(usize, UserImportRow)>, RowErrors) {
+    let parsed: Vec<Option<UserImportRow>> = match format {
+        ImportFormat::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(body)
+            .deserialize()
+            .map(|row| row.ok())
+            .collect(),
+        ImportFormat::Ndjson => body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|line| serde_json::from_slice(line).ok())
+            .collect(),
+    };
+
+    let mut rows = Vec::with_capacity(parsed.len());
+    let mut errors = HashMap::new();
+    let mut emails = HashSet::new();
+    for (idx, row) in parsed.into_iter().enumerate() {
+        let number = idx + 1;
+        let Some(row) = row else {
+            errors.insert(number.to_string(), Cow::Borrowed("invalid row"));
+            continue;
+        };
+        if let Err(err) = row.validate() {
+            let ErrorResponse {
+                errors: Some(field_errors),
+                ..
+            } = ErrorResponse::from(err)
+            else {
+                continue;
+            };
+            for (field, message) in field_errors {
+                errors.insert(format!("{}.{}", number, field), message);
+            }
+            continue;
+        }
+        let email = row.email.as_deref().unwrap().to_lowercase();
+        if !emails.insert(email) {
+            errors.insert(
+                format!("{}.email", number),
+                Cow::Borrowed("duplicated in import"),
+            );
+            continue;
+        }
+        rows.push((number, row));
+    }
+    (rows, errors)
+}
+
+// Generate ids, usernames and password hashes, this is CPU bound and
+// should run on a blocking thread. A password that cannot be hashed is
+// reported as an error of its row
+pub fn prepare_users(
+    rows: Vec<(usize, UserImportRow)>,
+) -> (Vec<NewUser>, RowErrors) {
+    let mut users = Vec::with_capacity(rows.len());
+    let mut errors = RowErrors::new();
+    for (number, row) in rows {
+        let id = uuid7_b62();
+        let email = row.email.unwrap();
+        let first_name = row
+            .first_name
+            .unwrap_or_else(|| email.split('@').next().unwrap().to_string());
+        let password = match row.password {
+            Some(password) => match generate_password_hash(
+                &password,
+                &id,
+                *PASSWORD_ITERATION,
+            ) {
+                Some(hash) => hash,
+                None => {
+                    errors.insert(
+                        format!("{}.password", number),
+                        Cow::Borrowed("failed to hash password"),
+                    );
+                    continue;
+                }
+            },
+            None => String::new(),
+        };
+        users.push(NewUser {
+            username: generate_username(&first_name, &id),
+            id,
+            email,
+            first_name,
+            last_name: row.last_name,
+            password,
+        });
+    }
+    (users, errors)
+}
@@ -1,5 +1,11 @@
+pub mod auth;
+mod avatar;
 mod db;
+mod export;
+mod import;
 pub mod models;
+mod privacy;
 pub mod routes;
 mod schema;
+pub mod tasks;
 pub mod views;
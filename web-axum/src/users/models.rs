@@ -2,11 +2,13 @@ use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::borrow::Cow;
 
+use crate::common::from_row::FromRow;
+use crate::common::to_sql::ToSqlInsert;
 use crate::common::utils::{
     Password::generate_password_hash, PASSWORD_ITERATION,
 };
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, FromRow)]
 pub struct User<'a> {
     pub id: Option<Cow<'a, str>>,
     pub email: Option<&'a str>,
@@ -26,9 +28,72 @@ pub struct User<'a> {
     pub update_at: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_login: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[column(default)]
+    pub login_count: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[column(default)]
+    pub last_login_ip: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[column(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportResponse {
+    pub total: usize,
+    pub imported: u64,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserCreated {
+    pub id: String,
+    pub create_at: DateTime<Utc>,
+}
+
+// Lowercased email of a row written by `users::db::insert_users`
+#[derive(Debug, FromRow)]
+pub struct UserInserted {
+    pub email: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DataExportResponse {
+    pub url: String,
+    pub expire_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErasureResponse {
+    pub erase_at: DateTime<Utc>,
+}
+
+// User ready to be inserted, see `users::db::insert_users`
+#[derive(Debug, ToSqlInsert)]
+#[sql(table = "users")]
+pub struct NewUser {
+    pub id: String,
+    pub email: String,
+    pub username: String,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub password: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LoginResponse<'a> {
+    pub token: String,
+    pub user: User<'a>,
 }
 
 impl User<'_> {
+    // Login details are only shown to the user themselves and admins
+    pub fn hide_login_details(&mut self) {
+        self.login_count = None;
+        self.last_login_ip = None;
+    }
+
     pub fn get_password_hash(password: &str, salt_str: &str) -> String {
         generate_password_hash(password, salt_str, *PASSWORD_ITERATION).unwrap()
     }
@@ -0,0 +1,132 @@
+use axum::body::Bytes;
+use std::io::{Cursor, Write};
+use tracing::error;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::common::error::{AppError, Result};
+use crate::common::utils::session_token;
+use crate::db::extractors::ConnectionPooled;
+use crate::storage::BLOB_STORE;
+use crate::users::avatar::remove_avatar;
+use crate::users::db::{
+    create_data_export, erase_user, get_user_data, get_user_settings,
+    purge_expired_data_exports, purge_user, users_due_for_erasure,
+    users_due_for_purge,
+};
+use crate::users::models::DataExportResponse;
+
+// Bundle the user's records as JSON files into a zip archive, store it and
+// return a link to download it
+pub async fn export_user_data(
+    con: &ConnectionPooled,
+    user_id: &str,
+) -> Result<DataExportResponse> {
+    let mut files = get_user_data(con, user_id).await?;
+    let settings = get_user_settings(con, user_id).await?;
+    files.push((
+        "settings.json",
+        serde_json::to_value(settings)
+            .map_err(|_| AppError::UnexpectedError)?,
+    ));
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        archive
+            .start_file(name, options)
+            .map_err(|_| AppError::UnexpectedError)?;
+        let content = serde_json::to_vec_pretty(&content)
+            .map_err(|_| AppError::UnexpectedError)?;
+        archive
+            .write_all(&content)
+            .map_err(|_| AppError::UnexpectedError)?;
+    }
+    let archive = archive
+        .finish()
+        .map_err(|_| AppError::UnexpectedError)?
+        .into_inner();
+
+    // the link is public, the random token is what keeps it private
+    let key = format!("exports/{}/{}.zip", user_id, session_token());
+    let url = BLOB_STORE
+        .put(&key, "application/zip", Bytes::from(archive))
+        .await?;
+    let expire_at = match create_data_export(con, user_id, &key, &url).await {
+        Ok(expire_at) => expire_at,
+        Err(err) => {
+            // nothing references the archive, don't leave it behind
+            if BLOB_STORE.delete(&key).await.is_err() {
+                error!("Export user data - failed to remove {}", key);
+            }
+            return Err(err);
+        }
+    };
+    Ok(DataExportResponse { url, expire_at })
+}
+
+// Anonymize accounts whose cooling-off period is over and drop the
+// archives that are no longer downloadable
+pub async fn erase_due_users(con: &mut ConnectionPooled) -> Result<usize> {
+    for key in purge_expired_data_exports(con).await? {
+        if BLOB_STORE.delete(&key).await.is_err() {
+            error!("Purge data exports - failed to remove {}", key);
+        }
+    }
+
+    let mut erased = 0;
+    for user_id in users_due_for_erasure(con).await? {
+        // one failing account must not hold back the others
+        let (image, exports) = match erase_user(con, &user_id).await {
+            Ok(blobs) => blobs,
+            Err(_) => {
+                error!("Erase user - failed to erase {}", user_id);
+                continue;
+            }
+        };
+        erased += 1;
+        remove_user_blobs(&user_id, image, exports).await;
+    }
+    Ok(erased)
+}
+
+// Hard delete users soft deleted for longer than `retention_days`
+pub async fn purge_deleted_users(
+    con: &mut ConnectionPooled,
+    retention_days: i32,
+) -> Result<usize> {
+    let mut purged = 0;
+    for user_id in users_due_for_purge(con, retention_days).await? {
+        let (image, exports) =
+            match purge_user(con, &user_id, retention_days).await {
+                Ok(Some(blobs)) => blobs,
+                Ok(None) => continue,
+                Err(_) => {
+                    error!("Purge user - failed to purge {}", user_id);
+                    continue;
+                }
+            };
+        purged += 1;
+        remove_user_blobs(&user_id, image, exports).await;
+    }
+    Ok(purged)
+}
+
+// Avatar and data export blobs of an erased or purged user
+async fn remove_user_blobs(
+    user_id: &str,
+    image: Option<String>,
+    exports: Vec<String>,
+) {
+    if let Some(image) = image {
+        if remove_avatar(user_id, &image).await.is_err() {
+            error!("Remove user blobs - failed to remove image {}", image);
+        }
+    }
+    for key in exports {
+        if BLOB_STORE.delete(&key).await.is_err() {
+            error!("Remove user blobs - failed to remove {}", key);
+        }
+    }
+}
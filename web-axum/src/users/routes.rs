@@ -1,16 +1,60 @@
+use crate::common::utils::{AVATAR_MAX_SIZE, IMPORT_MAX_SIZE};
+use crate::db::transaction::{transaction, TransactionConfig};
 use crate::users::views::{
-    delete_user, edit_user, password_login, user_list, user_register,
+    activate_user, deactivate_user, delete_user, edit_user, me_cancel_erasure,
+    me_change_settings, me_change_username, me_detail, me_export,
+    me_request_erasure, me_settings, merge_user, password_login, patch_user,
+    replace_user, restore_user, upload_image, user_by_username, user_detail,
+    user_export, user_import, user_list, user_register, user_search,
 };
 use crate::ConnectionPool;
-use axum::routing::{delete, get, patch, post, Router};
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::{delete, get, patch, post, put, Router};
+use tokio_postgres::IsolationLevel;
 
 pub fn auth_routes() -> Router<ConnectionPool> {
     Router::new()
         .route("/auth/password", post(password_login))
         .route("/auth/register", post(user_register))
         .route("/list", get(user_list))
-        .route("/:user_id/change", patch(edit_user))
+        .route("/search", post(user_search))
+        .route(
+            "/import",
+            post(user_import).layer(DefaultBodyLimit::max(*IMPORT_MAX_SIZE)),
+        )
+        .route("/export", get(user_export))
+        .route("/me", get(me_detail))
+        .route("/me/username", put(me_change_username))
+        .route("/me/export", post(me_export))
+        .route(
+            "/me/erasure",
+            post(me_request_erasure).delete(me_cancel_erasure),
+        )
+        .route("/me/settings", get(me_settings).patch(me_change_settings))
+        .route("/by-username/:username", get(user_by_username))
+        .route(
+            "/me/image",
+            // allow some room for the multipart boundaries and headers
+            post(upload_image)
+                .layer(DefaultBodyLimit::max(*AVATAR_MAX_SIZE + 16 * 1024)),
+        )
+        .route(
+            "/:user_id",
+            get(user_detail).put(replace_user).patch(patch_user),
+        )
+        .route(
+            "/:user_id/change",
+            patch(edit_user).layer(middleware::from_fn_with_state(
+                TransactionConfig::new(IsolationLevel::Serializable),
+                transaction,
+            )),
+        )
         .route("/:user_id/delete", delete(delete_user))
+        .route("/:user_id/restore", post(restore_user))
+        .route("/:user_id/activate", post(activate_user))
+        .route("/:user_id/deactivate", post(deactivate_user))
+        .route("/:user_id/merge", post(merge_user))
 }
 
 /*
@@ -1,12 +1,23 @@
 use chrono::{DateTime, Utc};
 use core::fmt::Debug;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use validator::{Validate, ValidationError};
 
-use crate::common::response::PaginationOptions;
-use crate::common::to_sql::ToSqlString;
-use crate::common::utils::EMAIL_SUFFIX;
+use crate::common::error::AppError;
+use crate::common::filter::FilterExpr;
+use crate::common::patch::merge_patch;
+use crate::common::response::{ErrorResponse, PaginationOptions};
+use crate::common::to_sql::{ToSqlString, ToSqlUpdate};
+use crate::common::utils::{
+    EMAIL_SUFFIX, LOCALE_FORMAT, RESERVED_USERNAMES, TIMEZONE_FORMAT,
+    USERNAME_FORMAT,
+};
+use crate::users::export::ExportFormat;
 
 #[derive(Deserialize, Debug)]
 pub struct UserProfile {
@@ -21,8 +32,8 @@ pub struct UserPasswordLogin {
         length(min = 5, max = 60, message = "invalid field length"),
         regex(path = "EMAIL_SUFFIX", message = "invalid email format")
     )]
-    email: String,
-    password: String,
+    pub email: String,
+    pub password: String,
 }
 
 #[derive(Debug, Validate, Deserialize, Serialize)]
@@ -54,6 +65,31 @@ pub struct RegisterEmail {
     new_password: Option<String>,
 }
 
+// Row of a bulk import, same rules as `RegisterEmail` with an optional
+// password since imported users may sign in through another provider
+#[derive(Debug, Validate, Deserialize)]
+pub struct UserImportRow {
+    #[validate(length(max = 50, message = "invalid field length"))]
+    pub first_name: Option<String>,
+    #[validate(length(max = 50, message = "invalid field length"))]
+    pub last_name: Option<String>,
+    #[validate(
+        email(message = "invalid email value"),
+        length(min = 5, max = 60, message = "invalid field length"),
+        regex(path = "EMAIL_SUFFIX", message = "invalid email format"),
+        required(message = "field is required")
+    )]
+    pub email: Option<String>,
+    #[validate(length(min = 5, max = 100, message = "invalid field length"))]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct EmailChange {
     email: String,
@@ -63,17 +99,116 @@ pub struct EmailChange {
 
 #[derive(Debug, Deserialize, Validate, Serialize, ToSqlString)]
 pub struct UserQuery {
+    #[validate(length(max = 100, message = "too many values"))]
+    #[filter(sortable)]
+    pub id: Option<Vec<String>>,
     #[validate(length(min = 3, max = 100, message = "invalid field length"))]
+    #[filter(op = "ilike", sortable)]
     pub email: Option<String>,
     pub is_active: Option<bool>,
     #[validate(length(min = 3, max = 50, message = "invalid field length"))]
     pub first_name: Option<String>,
     #[validate(length(min = 3, max = 50, message = "invalid field length"))]
     pub last_name: Option<String>,
+    #[filter(sortable)]
     pub create_at: Option<DateTime<Utc>>,
+    #[serde(rename = "create_at__gte")]
+    #[filter(op = "gte", column = "create_at")]
+    pub create_at_gte: Option<DateTime<Utc>>,
+    #[serde(rename = "create_at__lte")]
+    #[filter(op = "lte", column = "create_at")]
+    pub create_at_lte: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
+pub struct UserListOptions {
+    #[serde(default)]
+    pub include_deleted: bool,
+    #[validate(length(max = 2000, message = "invalid field length"))]
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserSearch {
+    pub filter: FilterExpr,
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub format: ExportFormat,
+    #[serde(default)]
+    pub include_deleted: bool,
+    #[validate(length(max = 2000, message = "invalid field length"))]
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserActivate {
+    #[validate(length(min = 3, max = 255, message = "invalid field length"))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserMerge {
+    #[validate(length(min = 20, max = 255, message = "invalid user id"))]
+    pub source_id: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserDeactivate {
+    #[validate(
+        length(min = 3, max = 255, message = "invalid field length"),
+        required(message = "field is required")
+    )]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UsernameChange {
+    #[validate(
+        length(min = 3, max = 30, message = "invalid field length"),
+        regex(path = "USERNAME_FORMAT", message = "invalid username format"),
+        custom(
+            function = "validate_not_reserved",
+            message = "username is reserved"
+        )
+    )]
+    pub username: String,
+}
+
+fn validate_not_reserved(username: &str) -> Result<(), ValidationError> {
+    if RESERVED_USERNAMES.contains(&username.to_lowercase().as_str()) {
+        return Err(ValidationError::new("reserved"));
+    }
+    Ok(())
+}
+
+fn validate_username_absent(_: &str) -> Result<(), ValidationError> {
+    Err(ValidationError::new("username"))
+}
+
+// Editable user fields, used by full replace and merge patch. The image is
+// only set by the upload endpoint and the username through `UsernameChange`
+// (`/me/username`) so aliases and the cooldown apply, sending it is an error
+#[derive(Debug, Deserialize, Serialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct UserEdit {
+    #[serde(default, skip_serializing)]
+    #[validate(custom(
+        function = "validate_username_absent",
+        message = "username is changed through /me/username"
+    ))]
+    pub username: Option<String>,
+    #[validate(length(max = 50, message = "invalid field length"))]
+    pub first_name: Option<String>,
+    #[validate(length(max = 50, message = "invalid field length"))]
+    pub last_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSqlUpdate)]
 pub struct ProfileChange {
     #[serde(default, with = "::serde_with::rust::double_option")]
     pub first_name: Option<Option<String>>,
@@ -81,3 +216,193 @@ pub struct ProfileChange {
     #[validate(length(min = 3, max = 50, message = "invalid field length"))]
     pub last_name: Option<Option<String>>,
 }
+
+// Registered user settings, keys missing from the stored document fall
+// back to their default value
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
+#[serde(default)]
+pub struct UserSettings {
+    #[validate(regex(path = "LOCALE_FORMAT", message = "invalid locale"))]
+    pub locale: String,
+    #[validate(
+        length(max = 64, message = "invalid field length"),
+        regex(path = "TIMEZONE_FORMAT", message = "invalid timezone")
+    )]
+    pub timezone: String,
+    pub notifications: NotificationSettings,
+}
+
+type SettingCheck = fn(&Value) -> bool;
+
+// Schema of every registered setting key, checking the merged value of a
+// patched key deserializes into its type
+static SETTINGS_SCHEMA: [(&str, SettingCheck); 3] = [
+    ("locale", is_setting::<String>),
+    ("timezone", is_setting::<String>),
+    ("notifications", is_setting::<NotificationSettings>),
+];
+
+fn is_setting<T: DeserializeOwned>(value: &Value) -> bool {
+    T::deserialize(value).is_ok()
+}
+
+impl UserSettings {
+    // Apply a merge patch, rejecting keys missing from `SETTINGS_SCHEMA`
+    // and values not matching their schema. A `null` resets the default
+    pub fn patched(&self, patch: &Value) -> Result<Self, AppError> {
+        let Value::Object(keys) = patch else {
+            return Err(AppError::from(ErrorResponse::create_error(
+                "Invalid patch document",
+            )));
+        };
+        let mut document = serde_json::to_value(self)
+            .map_err(|_| AppError::UnexpectedError)?;
+        merge_patch(&mut document, patch);
+
+        let mut errors: HashMap<String, Cow<'static, str>> = HashMap::new();
+        for key in keys.keys() {
+            match SETTINGS_SCHEMA.iter().find(|(name, _)| name == key) {
+                None => {
+                    errors
+                        .insert(key.clone(), Cow::Borrowed("unknown setting"));
+                }
+                Some((_, is_valid)) => {
+                    if let Some(value) = document.get(key) {
+                        if !is_valid(value) {
+                            errors.insert(
+                                key.clone(),
+                                Cow::Borrowed("invalid setting"),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(AppError::from(ErrorResponse {
+                errors: Some(errors),
+                error: None,
+            }));
+        }
+
+        let settings: Self = serde_json::from_value(document)
+            .map_err(|_| AppError::UnexpectedError)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            locale: "en".to_string(),
+            timezone: "UTC".to_string(),
+            notifications: NotificationSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    pub email: bool,
+    pub push: bool,
+    pub marketing: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            email: true,
+            push: true,
+            marketing: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error_keys(result: Result<UserSettings, AppError>) -> Vec<String> {
+        match result {
+            Err(AppError::ErrorResponse(ErrorResponse {
+                errors: Some(errors),
+                ..
+            })) => {
+                let mut keys: Vec<String> = errors.into_keys().collect();
+                keys.sort();
+                keys
+            }
+            _ => panic!("expected per key errors"),
+        }
+    }
+
+    #[test]
+    fn schema_covers_every_setting() {
+        let Value::Object(document) =
+            serde_json::to_value(UserSettings::default()).unwrap()
+        else {
+            panic!("settings must serialize to an object");
+        };
+        let mut keys: Vec<&str> = document.keys().map(String::as_str).collect();
+        let mut registered: Vec<&str> =
+            SETTINGS_SCHEMA.iter().map(|(key, _)| *key).collect();
+        keys.sort();
+        registered.sort();
+        assert_eq!(keys, registered);
+    }
+
+    #[test]
+    fn patch_merges_nested_settings() {
+        let settings = UserSettings::default()
+            .patched(&json!({"notifications": {"marketing": true}}))
+            .ok()
+            .unwrap();
+        assert!(settings.notifications.marketing);
+        assert!(settings.notifications.email);
+
+        let settings = settings
+            .patched(&json!({"notifications": null, "locale": "pt-BR"}))
+            .ok()
+            .unwrap();
+        assert_eq!(settings.notifications, NotificationSettings::default());
+        assert_eq!(settings.locale, "pt-BR");
+    }
+
+    #[test]
+    fn patch_rejects_unknown_and_mistyped_keys() {
+        let settings = UserSettings::default();
+        assert_eq!(
+            error_keys(settings.patched(&json!({
+                "theme": "dark",
+                "timezone": 3,
+                "notifications": {"sms": true},
+            }))),
+            vec!["notifications", "theme", "timezone"]
+        );
+        assert!(settings.patched(&json!("dark")).is_err());
+    }
+
+    #[test]
+    fn user_edit_leaves_image_and_username_out() {
+        let edit = |value| serde_json::from_value::<UserEdit>(value);
+        assert!(edit(json!({"image": "avatars/u2/a/original.png"})).is_err());
+
+        let with_username = edit(json!({"username": "alice"})).unwrap();
+        let errors = with_username.validate().unwrap_err();
+        assert!(errors.field_errors().contains_key("username"));
+
+        let current = edit(json!({"first_name": "Ann"})).unwrap();
+        assert!(current.validate().is_ok());
+        assert_eq!(
+            serde_json::to_value(&current).unwrap(),
+            json!({"first_name": "Ann", "last_name": null})
+        );
+        let patch = json!({"username": "alice"});
+        assert!(
+            crate::common::patch::apply_merge_patch(&current, &patch).is_err()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::common::utils::{DELETED_USER_RETENTION_DAYS, USER_PURGE_INTERVAL};
+use crate::db::extractors::ConnectionPool;
+use crate::users::db::purge_expired_username_aliases;
+use crate::users::privacy::{erase_due_users, purge_deleted_users};
+
+// Periodically hard delete users whose soft delete is older than the
+// configured retention period, along with expired username aliases, and
+// anonymize users whose erasure cooling-off period is over.
+pub async fn purge_deleted_users_task(pool: ConnectionPool) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(*USER_PURGE_INTERVAL));
+    loop {
+        interval.tick().await;
+        let mut conn = match pool.get_owned().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("Purge deleted users - connection error {:?}", err);
+                continue;
+            }
+        };
+        let retention_days = *DELETED_USER_RETENTION_DAYS;
+        match purge_deleted_users(&mut conn, retention_days).await {
+            Ok(0) => {}
+            Ok(count) => info!("Purged {} deleted users", count),
+            Err(_) => error!("Purge deleted users - failed to purge"),
+        }
+        if purge_expired_username_aliases(&conn).await.is_err() {
+            error!("Purge username aliases - failed to purge");
+        }
+        match erase_due_users(&mut conn).await {
+            Ok(0) => {}
+            Ok(count) => info!("Erased {} users", count),
+            Err(_) => error!("Erase users - failed to erase"),
+        }
+    }
+}
@@ -1,28 +1,105 @@
+use crate::audit::record_event;
 use crate::common::error::{AppError, Result};
-use crate::common::extractor::{JSONValidate, QueryValidate};
+use crate::common::etag::{version_etag, IfMatch, IfNoneMatch};
+use crate::common::extractor::{
+    JSONValidate, MergePatch, QueryValidate, ValidateRejection,
+};
+use crate::common::filter::FilterExpr;
+use crate::common::from_row::FromRow;
+use crate::common::patch::apply_merge_patch;
 use crate::common::response::{ErrorResponse, ListResponse, PaginationOptions};
-use crate::db::extractors::{ConnectionPool, DatabaseConnection};
-use crate::db::query::Builder;
+use crate::common::sort::{SortKey, SortOptions};
+use crate::common::utils::{Password, AVATAR_MAX_SIZE, IMPORT_BATCH_SIZE};
+use crate::db::extractors::{
+    ConnectionPool, ConnectionPooled, DatabaseConnection,
+};
+use crate::db::query::{Builder, InsertBuilder};
+use crate::db::transaction::DatabaseTransaction;
+use crate::users::auth::{AdminUser, AuthUser, SessionUser};
+use crate::users::avatar::{remove_avatar, store_avatar};
 use crate::users::schema::{
-    ProfileChange, RegisterEmail, UserPasswordLogin, UserQuery,
+    ExportOptions, ImportOptions, ProfileChange, RegisterEmail, UserActivate,
+    UserDeactivate, UserEdit, UserListOptions, UserMerge, UserPasswordLogin,
+    UserQuery, UserSearch, UsernameChange,
+};
+use crate::users::{
+    db::{
+        cancel_erasure, change_username, create_session, create_user,
+        existing_emails, get_user, get_user_by_username, get_user_settings,
+        insert_users, lock_user_settings, merge_users, record_login,
+        request_erasure, resolve_user_tombstone, resolve_username_alias,
+        revoke_user_sessions, save_user_settings, set_user_active, update_user,
+    },
+    export::{export_users, EXPORT_COLUMNS},
+    import::{parse_rows, prepare_users, ImportFormat},
+    models::{ErasureResponse, ImportResponse, LoginResponse, NewUser, User},
+    privacy::export_user_data,
 };
-use crate::users::{db::create_user, models::User};
 use axum::{
-    debug_handler, extract::Path, http::StatusCode, response::IntoResponse,
+    body::Bytes,
+    debug_handler,
+    extract::{ConnectInfo, Multipart, Path},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, ETAG},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Redirect, Response},
     Json,
 };
+use serde_json::{json, Map, Value};
 use std::borrow::Cow;
-use tokio_postgres::types::ToSql;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use tokio_postgres::GenericClient;
+use tracing::{error, info};
 
-use crate::common::to_sql::ToSqlString;
+use crate::common::to_sql::{SqlParam, ToSqlString, ToSqlUpdate};
 
-#[debug_handler]
+#[debug_handler(state=ConnectionPool)]
 pub async fn password_login(
-    Json(payload): Json<UserPasswordLogin>,
-) -> impl IntoResponse {
-    println!("Payload {:?}", payload);
-    "OK"
+    DatabaseConnection(conn): DatabaseConnection,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    JSONValidate(payload): JSONValidate<UserPasswordLogin>,
+) -> Result<impl IntoResponse> {
+    let row = conn
+        .query_opt(
+            "SELECT id, email, image, username, first_name, last_name, \
+            is_active, create_at, update_at, last_login, password \
+            FROM users WHERE lower(email) = lower($1) \
+            AND deleted_at IS NULL",
+            &[&payload.email],
+        )
+        .await?
+        .ok_or_else(|| {
+            AppError::from(ErrorResponse::create_error(
+                "Invalid email or password",
+            ))
+        })?;
+
+    let password_hash: &str = row.get(10);
+    if password_hash.is_empty()
+        || !Password::is_valid(&payload.password, password_hash)
+    {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid email or password",
+        )));
+    }
+
+    let is_active: Option<bool> = row.get(6);
+    if !is_active.unwrap_or(false) {
+        return Err(AppError::Forbidden("User is inactive".to_string()));
+    }
+
+    let user_id: &str = row.get(0);
+    let login = record_login(&conn, user_id, address.ip()).await?;
+
+    let mut user = User::from_row(&row)?;
+    user.last_login = login.get(0);
+    user.login_count = login.get(1);
+    user.last_login_ip = login.get(2);
+    let token = create_session(&conn, user.id.as_ref().unwrap()).await?;
+
+    Ok(Json(LoginResponse { token, user }).into_response())
 }
 
 #[debug_handler(state=ConnectionPool)]
@@ -41,53 +118,210 @@ pub async fn user_register(
     Ok(Json(user).into_response())
 }
 
+#[debug_handler(state=ConnectionPool)]
+pub async fn user_import(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    AdminUser(_): AdminUser,
+    QueryValidate(options): QueryValidate<ImportOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    let Some(format) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(ImportFormat::from_content_type)
+    else {
+        return Ok(ValidateRejection::UnsupportedMediaType.into_response());
+    };
+
+    let (rows, mut errors) = parse_rows(format, &body);
+    let emails: Vec<String> = rows
+        .iter()
+        .map(|(_, row)| row.email.as_deref().unwrap().to_lowercase())
+        .collect();
+    let existing = existing_emails(&conn, &emails).await?;
+    for (number, row) in rows.iter() {
+        let email = row.email.as_deref().unwrap().to_lowercase();
+        if existing.contains(&email) {
+            errors.insert(
+                format!("{}.email", number),
+                Cow::Borrowed("already exists"),
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(AppError::from(ErrorResponse {
+            errors: Some(errors),
+            error: None,
+        }));
+    }
+
+    let total = rows.len();
+    if options.dry_run {
+        return Ok(Json(ImportResponse {
+            total,
+            imported: 0,
+            dry_run: true,
+        })
+        .into_response());
+    }
+
+    let numbers: HashMap<String, usize> = rows
+        .iter()
+        .map(|(number, row)| {
+            (row.email.as_deref().unwrap().to_lowercase(), *number)
+        })
+        .collect();
+    let (users, mut errors) =
+        tokio::task::spawn_blocking(move || prepare_users(rows))
+            .await
+            .map_err(|_| AppError::UnexpectedError)?;
+    if !errors.is_empty() {
+        return Err(AppError::from(ErrorResponse {
+            errors: Some(errors),
+            error: None,
+        }));
+    }
+
+    // keep every batch under the bind parameter limit
+    let batch_size =
+        (*IMPORT_BATCH_SIZE).clamp(1, InsertBuilder::<NewUser>::max_rows());
+    let transaction = conn.transaction().await?;
+    let mut imported = 0;
+    for batch in users.chunks(batch_size) {
+        let inserted: HashSet<String> = insert_users(&transaction, batch)
+            .await?
+            .into_iter()
+            .map(|user| user.email)
+            .collect();
+        imported += inserted.len() as u64;
+        // rows created concurrently since the `existing_emails` check
+        for user in batch {
+            let email = user.email.to_lowercase();
+            if !inserted.contains(&email) {
+                errors.insert(
+                    format!("{}.email", numbers[&email]),
+                    Cow::Borrowed("already exists"),
+                );
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::from(ErrorResponse {
+            errors: Some(errors),
+            error: None,
+        }));
+    }
+    transaction.commit().await?;
+
+    Ok(Json(ImportResponse {
+        total,
+        imported,
+        dry_run: false,
+    })
+    .into_response())
+}
+
 #[debug_handler(state=ConnectionPool)]
 pub async fn user_list(
     DatabaseConnection(conn): DatabaseConnection,
     QueryValidate(filter): QueryValidate<UserQuery>,
     QueryValidate(pagination): QueryValidate<PaginationOptions>,
+    QueryValidate(sort): QueryValidate<SortOptions>,
+    QueryValidate(options): QueryValidate<UserListOptions>,
+    admin: Option<AdminUser>,
 ) -> Result<impl IntoResponse> {
-    let (query, mut query_param) =
-        filter.as_sql_string("ILIKE", "AND", "id DESC");
+    if options.include_deleted && admin.is_none() {
+        return Err(AppError::Forbidden("Admin access required".to_string()));
+    }
+    let keys = sort.keys(UserQuery::SORT_COLUMNS)?;
+    let (mut query, mut query_param) = filter.as_sql_string("ILIKE", "AND");
+
+    if let Some(expr) = options.filter.as_deref() {
+        FilterExpr::parse(expr)?.push_where(
+            &mut query,
+            UserQuery::FILTER_COLUMNS,
+            &mut query_param,
+        )?;
+    }
+    if !options.include_deleted {
+        query += if query.is_empty() { "WHERE" } else { "AND" };
+        query += " deleted_at IS NULL ";
+    }
 
+    let show_login = admin.is_some();
+    list_users(&conn, query, query_param, &keys, pagination, show_login).await
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn user_search(
+    DatabaseConnection(conn): DatabaseConnection,
+    QueryValidate(pagination): QueryValidate<PaginationOptions>,
+    QueryValidate(sort): QueryValidate<SortOptions>,
+    admin: Option<AdminUser>,
+    JSONValidate(payload): JSONValidate<UserSearch>,
+) -> Result<impl IntoResponse> {
+    if payload.include_deleted && admin.is_none() {
+        return Err(AppError::Forbidden("Admin access required".to_string()));
+    }
+    let keys = sort.keys(UserQuery::SORT_COLUMNS)?;
+    let mut query = String::new();
+    let mut query_param = Vec::new();
+    payload.filter.push_where(
+        &mut query,
+        UserQuery::FILTER_COLUMNS,
+        &mut query_param,
+    )?;
+    if !payload.include_deleted {
+        query += "AND deleted_at IS NULL ";
+    }
+
+    let show_login = admin.is_some();
+    list_users(&conn, query, query_param, &keys, pagination, show_login).await
+}
+
+async fn list_users(
+    conn: &ConnectionPooled,
+    query: String,
+    mut query_param: Vec<SqlParam>,
+    keys: &[SortKey],
+    pagination: PaginationOptions,
+    show_login: bool,
+) -> Result<Response> {
     let mut query_str =
         "select id, email, image, username, first_name, last_name, \
-    is_active, create_at, update_at, last_login from users "
+    is_active, create_at, update_at, last_login, deleted_at, login_count, \
+    host(last_login_ip) AS last_login_ip from users "
             .to_string()
             + query.as_str();
-    let (rows, has_next) = Builder::query(
-        &conn,
+    let page = Builder::query(
+        conn,
         &mut query_str,
         &mut query_param,
-        None,
+        keys,
         Some(&pagination),
     )
     .await?;
 
-    // println!("Query: {}", query_str);
-    // println!("Query Param: {:?}", query_param);
-    let users: Vec<User> = rows
-        [..rows.len().min(pagination.limit.unwrap() as usize)]
+    let users: Vec<User> = page
+        .rows
         .iter()
-        .map(|row| User {
-            id: Some(Cow::Borrowed(row.get(0))),
-            email: Some(row.get(1)),
-            image: row.get(2),
-            username: Some(Cow::Borrowed(row.get(3))),
-            first_name: row.get(4),
-            last_name: row.get(5),
-            is_active: Some(row.get(6)),
-            create_at: row.get(7),
-            update_at: row.get(8),
-            last_login: row.get(9),
+        .map(|row| {
+            let mut user = User::from_row(row)?;
+            if !show_login {
+                user.hide_login_details();
+            }
+            Ok(user)
         })
-        .collect();
+        .collect::<Result<_>>()?;
 
     Ok(Json(ListResponse {
         data: users,
         pagination: PaginationOptions {
-            next: None,
-            has_next: Some(has_next),
+            has_next: Some(page.next.is_some()),
+            next: page.next,
+            prev: page.prev,
             limit: pagination.limit,
         },
     })
@@ -95,10 +329,53 @@ pub async fn user_list(
 }
 
 #[debug_handler(state=ConnectionPool)]
-pub async fn edit_user(
+pub async fn user_export(
     DatabaseConnection(conn): DatabaseConnection,
+    AdminUser(_): AdminUser,
+    QueryValidate(filter): QueryValidate<UserQuery>,
+    QueryValidate(options): QueryValidate<ExportOptions>,
+) -> Result<impl IntoResponse> {
+    let (mut query, mut query_param) = filter.as_sql_string("ILIKE", "AND");
+
+    if let Some(expr) = options.filter.as_deref() {
+        FilterExpr::parse(expr)?.push_where(
+            &mut query,
+            UserQuery::FILTER_COLUMNS,
+            &mut query_param,
+        )?;
+    }
+    if !options.include_deleted {
+        query += if query.is_empty() { "WHERE" } else { "AND" };
+        query += " deleted_at IS NULL ";
+    }
+
+    let query_str =
+        format!("select {} from users {} ORDER BY id", EXPORT_COLUMNS, query);
+    let format = options.format;
+    let body = export_users(conn, query_str, query_param, format);
+
+    Ok((
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"users.{}\"",
+                    format.extension()
+                ),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn user_detail(
+    DatabaseConnection(conn): DatabaseConnection,
+    session: Option<SessionUser>,
     Path(user_id): Path<String>,
-    JSONValidate(payload): JSONValidate<ProfileChange>,
+    if_none_match: IfNoneMatch,
 ) -> Result<impl IntoResponse> {
     if user_id.len() < 20 {
         return Err(AppError::from(ErrorResponse::create_error(
@@ -106,91 +383,534 @@ pub async fn edit_user(
         )));
     }
 
-    let is_valid_user = conn
-        .query_one(
-            "SELECT EXISTS(SELECT 1 FROM users WHERE id=$1)",
-            &[&user_id],
+    let row = match get_user(&conn, &user_id).await {
+        // merged accounts redirect to the account they were merged into
+        Err(AppError::NotFound(_)) => {
+            return match resolve_user_tombstone(&conn, &user_id).await? {
+                Some(target) => {
+                    Ok(Redirect::temporary(&target).into_response())
+                }
+                None => Err(AppError::NotFound("User not found".to_string())),
+            };
+        }
+        result => result?,
+    };
+    let etag = version_etag(row.get(10));
+    if if_none_match.matches(&etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    let mut user = User::from_row(&row)?;
+    if session
+        .is_none_or(|session| session.ensure_owner_or_admin(&user_id).is_err())
+    {
+        user.hide_login_details();
+    }
+
+    Ok(([(ETAG, etag)], Json(user)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_detail(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+    if_none_match: IfNoneMatch,
+) -> Result<impl IntoResponse> {
+    let row = get_user(&conn, &user_id).await?;
+    let etag = version_etag(row.get(10));
+    if if_none_match.matches(&etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, etag)], Json(user)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn user_by_username(
+    DatabaseConnection(conn): DatabaseConnection,
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse> {
+    let Some(row) = get_user_by_username(&conn, &username).await? else {
+        // previous usernames redirect to the current one, relative to
+        // this route so it works under any mount point
+        return match resolve_username_alias(&conn, &username).await? {
+            Some(current) => Ok(Redirect::temporary(&current).into_response()),
+            None => Err(AppError::NotFound("User not found".to_string())),
+        };
+    };
+
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_change_username(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+    JSONValidate(payload): JSONValidate<UsernameChange>,
+) -> Result<impl IntoResponse> {
+    let row = change_username(&mut conn, &user_id, &payload.username).await?;
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_settings(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+) -> Result<impl IntoResponse> {
+    let settings = get_user_settings(&conn, &user_id).await?;
+    Ok(Json(settings).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_change_settings(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+    MergePatch(patch): MergePatch,
+) -> Result<impl IntoResponse> {
+    let transaction = conn.transaction().await?;
+    let current = lock_user_settings(&transaction, &user_id).await?;
+    let settings = current.patched(&patch)?;
+
+    let (Value::Object(current_value), Value::Object(settings_value)) = (
+        serde_json::to_value(&current)
+            .map_err(|_| AppError::UnexpectedError)?,
+        serde_json::to_value(&settings)
+            .map_err(|_| AppError::UnexpectedError)?,
+    ) else {
+        return Err(AppError::UnexpectedError);
+    };
+
+    let changes: Map<String, Value> = settings_value
+        .iter()
+        .filter(|(key, value)| current_value.get(*key) != Some(*value))
+        .map(|(key, value)| {
+            (
+                key.clone(),
+                json!({"from": current_value[key], "to": value}),
+            )
+        })
+        .collect();
+
+    if !changes.is_empty() {
+        save_user_settings(&transaction, &user_id, &settings).await?;
+        record_event(
+            &transaction,
+            Some(&user_id),
+            Some(&user_id),
+            "settings.update",
+            json!({ "changes": changes }),
         )
         .await?;
-    let value: bool = is_valid_user.get(0);
-
-    if !value {
-        return Err(AppError::NotFound("User not found".to_string()));
-    }
-
-    let mut idx = 1;
-    let mut fields = String::new();
-    let mut user_data: Vec<Box<(dyn ToSql + Sync + Send)>> = Vec::new();
-    if let Some(first_name) = payload.first_name.as_ref() {
-        fields += format!("first_name = ${}", idx).as_str();
-        idx += 1;
-        user_data.push(if first_name.is_none() {
-            Box::new(first_name)
-        } else {
-            Box::new(first_name.as_deref().unwrap())
-        });
+        transaction.commit().await?;
+    }
+
+    Ok(Json(settings).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_export(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+) -> Result<impl IntoResponse> {
+    let export = export_user_data(&conn, &user_id).await?;
+    record_event(
+        &*conn,
+        Some(&user_id),
+        Some(&user_id),
+        "privacy.export",
+        json!({ "expire_at": export.expire_at }),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(export)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_request_erasure(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+) -> Result<impl IntoResponse> {
+    let erase_at = request_erasure(&conn, &user_id).await?;
+    record_event(
+        &*conn,
+        Some(&user_id),
+        Some(&user_id),
+        "privacy.erasure_requested",
+        json!({ "erase_at": erase_at }),
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(ErasureResponse { erase_at }))
+        .into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn me_cancel_erasure(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+) -> Result<impl IntoResponse> {
+    if !cancel_erasure(&conn, &user_id).await? {
+        return Err(AppError::NotFound(
+            "No pending erasure request".to_string(),
+        ));
+    }
+    record_event(
+        &*conn,
+        Some(&user_id),
+        Some(&user_id),
+        "privacy.erasure_cancelled",
+        json!({}),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn upload_image(
+    DatabaseConnection(conn): DatabaseConnection,
+    AuthUser(user_id): AuthUser,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse> {
+    let mut image = None;
+    while let Some(field) = multipart.next_field().await.map_err(|_| {
+        AppError::from(ErrorResponse::create_error("Invalid multipart body"))
+    })? {
+        if field.name() == Some("image") {
+            image = Some(field.bytes().await.map_err(|_| {
+                AppError::from(ErrorResponse::create_error(
+                    "Invalid image upload",
+                ))
+            })?);
+            break;
+        }
+    }
+
+    let image = image.ok_or_else(|| {
+        AppError::from(ErrorResponse::create_error("Image is required"))
+    })?;
+    if image.len() > *AVATAR_MAX_SIZE {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Image exceeds allowed size",
+        )));
+    }
+
+    let image_url = store_avatar(&user_id, image).await?;
+    let row = conn
+        .query_one(
+            "UPDATE users u SET image = $1, version = u.version + 1 \
+            FROM (SELECT image FROM users WHERE id = $2) old \
+            WHERE u.id = $2 RETURNING u.id, u.email, u.image, u.username, \
+            u.first_name, u.last_name, u.is_active, u.create_at, \
+            u.update_at, u.last_login, old.image AS previous_image",
+            &[&image_url, &user_id],
+        )
+        .await;
+    let row = match row {
+        Ok(row) => row,
+        Err(err) => {
+            // the new avatar is not referenced by anything, drop it
+            if remove_avatar(&user_id, &image_url).await.is_err() {
+                error!("Failed to remove orphaned image {}", image_url);
+            }
+            return Err(err.into());
+        }
+    };
+
+    let previous_image: Option<&str> = row.get("previous_image");
+    if let Some(previous_image) = previous_image {
+        if remove_avatar(&user_id, previous_image).await.is_err() {
+            error!("Failed to remove previous image {}", previous_image);
+        }
+    }
+    let user = User::from_row(&row)?;
+
+    Ok(Json(user).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn replace_user(
+    DatabaseConnection(conn): DatabaseConnection,
+    session: SessionUser,
+    Path(user_id): Path<String>,
+    if_match: IfMatch,
+    JSONValidate(payload): JSONValidate<UserEdit>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+    session.ensure_owner_or_admin(&user_id)?;
+
+    let version: i32 = get_user(&conn, &user_id).await?.get(10);
+    if !if_match.matches(&version_etag(version)) {
+        return Err(AppError::PreconditionFailed);
+    }
+
+    let row = update_user(&conn, &user_id, version, &payload).await?;
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn patch_user(
+    DatabaseConnection(conn): DatabaseConnection,
+    session: SessionUser,
+    Path(user_id): Path<String>,
+    if_match: IfMatch,
+    MergePatch(patch): MergePatch,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+    session.ensure_owner_or_admin(&user_id)?;
+
+    let current = get_user(&conn, &user_id).await?;
+    let version: i32 = current.get(10);
+    if !if_match.matches(&version_etag(version)) {
+        return Err(AppError::PreconditionFailed);
+    }
+
+    let current = UserEdit {
+        username: None,
+        first_name: current.get(4),
+        last_name: current.get(5),
     };
+    let merged = apply_merge_patch(&current, &patch)?;
+
+    let row = update_user(&conn, &user_id, version, &merged).await?;
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
+}
 
-    if let Some(last_name) = payload.last_name {
-        fields += format!(
-            "{}last_name = ${}",
-            if fields.is_empty() { "" } else { ", " },
-            idx
+#[debug_handler(state=ConnectionPool)]
+pub async fn edit_user(
+    conn: DatabaseTransaction,
+    session: SessionUser,
+    Path(user_id): Path<String>,
+    if_match: IfMatch,
+    JSONValidate(payload): JSONValidate<ProfileChange>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+    session.ensure_owner_or_admin(&user_id)?;
+
+    let version: i32 = conn
+        .query_opt(
+            "SELECT version FROM users WHERE id=$1 AND deleted_at IS NULL",
+            &[&user_id],
         )
-        .as_str();
-        idx += 1;
-        user_data.push(if last_name.is_none() {
-            Box::new(last_name)
-        } else {
-            Box::new(last_name.unwrap())
-        });
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?
+        .get(0);
+
+    if !if_match.matches(&version_etag(version)) {
+        return Err(AppError::PreconditionFailed);
     }
 
-    user_data.push(Box::new(&user_id));
+    let (fields, mut query_params) = payload.as_sql_update(1)?;
+    let idx = query_params.len() + 1;
+    query_params.push(&user_id);
+    query_params.push(&version);
     let query = format!(
-        "Update users SET {} WHERE id = ${} RETURNING id, email, image, \
-    username, first_name, last_name, is_active, create_at, update_at, last_login ",
-        fields, idx
+        "UPDATE users SET {}, version = version + 1 WHERE id = ${} \
+        AND version = ${} RETURNING id, email, image, username, first_name, \
+        last_name, is_active, create_at, update_at, last_login, version",
+        fields,
+        idx,
+        idx + 1
     );
 
-    let query_params: Vec<&(dyn ToSql + Sync)> = user_data
-        .iter()
-        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
-        .collect();
-    let row = conn.query_one(query.as_str(), &query_params).await?;
-    let user = User {
-        id: Some(Cow::Borrowed(row.get(0))),
-        email: Some(row.get(1)),
-        image: row.get(2),
-        username: Some(Cow::Borrowed(row.get(3))),
-        first_name: row.get(4),
-        last_name: row.get(5),
-        is_active: Some(row.get(6)),
-        create_at: row.get(7),
-        update_at: row.get(8),
-        last_login: row.get(9),
-    };
+    let row = conn
+        .query_opt(query.as_str(), &query_params)
+        .await?
+        .ok_or(AppError::PreconditionFailed)?;
+    let user = User::from_row(&row)?;
 
-    Ok(Json(user).into_response())
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
 }
 
 #[debug_handler(state=ConnectionPool)]
 pub async fn delete_user(
     DatabaseConnection(conn): DatabaseConnection,
+    session: SessionUser,
     Path(user_id): Path<String>,
+    if_match: IfMatch,
 ) -> Result<impl IntoResponse> {
     if user_id.len() < 20 {
         return Err(AppError::from(ErrorResponse::create_error(
             "Invalid user id",
         )));
     }
+    session.ensure_owner_or_admin(&user_id)?;
+
+    let Some(row) = conn
+        .query_opt(
+            "SELECT version FROM users WHERE id=$1 AND deleted_at IS NULL",
+            &[&user_id],
+        )
+        .await?
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let version: i32 = row.get(0);
+    if !if_match.matches(&version_etag(version)) {
+        return Err(AppError::PreconditionFailed);
+    }
 
     let is_deleted: u64 = conn
-        .execute("DELETE FROM users WHERE id=$1", &[&user_id])
+        .execute(
+            "UPDATE users SET deleted_at = now(), version = version + 1 \
+            WHERE id=$1 AND version = $2 AND deleted_at IS NULL",
+            &[&user_id, &version],
+        )
         .await?;
 
     if is_deleted > 0 {
         return Ok(StatusCode::NO_CONTENT.into_response());
     }
-    Ok(StatusCode::NOT_FOUND.into_response())
+    Err(AppError::PreconditionFailed)
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn restore_user(
+    DatabaseConnection(conn): DatabaseConnection,
+    AdminUser(actor_id): AdminUser,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+
+    let row = conn
+        .query_opt(
+            "UPDATE users SET deleted_at = NULL, version = version + 1 \
+            WHERE id=$1 AND deleted_at IS NOT NULL RETURNING id, email, \
+            image, username, first_name, last_name, is_active, create_at, \
+            update_at, last_login",
+            &[&user_id],
+        )
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    record_event(
+        &*conn,
+        Some(&user_id),
+        Some(&actor_id),
+        "user.restore",
+        json!({}),
+    )
+    .await?;
+
+    let user = User::from_row(&row)?;
+
+    Ok(Json(user).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn activate_user(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    AdminUser(actor_id): AdminUser,
+    Path(user_id): Path<String>,
+    JSONValidate(payload): JSONValidate<UserActivate>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+
+    let transaction = conn.transaction().await?;
+    let row = set_user_active(&transaction, &user_id, true, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    record_event(
+        &transaction,
+        Some(&user_id),
+        Some(&actor_id),
+        "user.activate",
+        json!({ "reason": payload.reason }),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    let user = User::from_row(&row)?;
+
+    Ok(Json(user).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn deactivate_user(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    AdminUser(actor_id): AdminUser,
+    Path(user_id): Path<String>,
+    JSONValidate(payload): JSONValidate<UserDeactivate>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+
+    // the reason is kept on the user until it is activated again
+    let transaction = conn.transaction().await?;
+    let row = set_user_active(
+        &transaction,
+        &user_id,
+        false,
+        payload.reason.as_deref(),
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    revoke_user_sessions(&transaction, &user_id).await?;
+    record_event(
+        &transaction,
+        Some(&user_id),
+        Some(&actor_id),
+        "user.deactivate",
+        json!({ "reason": payload.reason }),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    let user = User::from_row(&row)?;
+
+    Ok(Json(user).into_response())
+}
+
+#[debug_handler(state=ConnectionPool)]
+pub async fn merge_user(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(user_id): Path<String>,
+    AdminUser(actor_id): AdminUser,
+    JSONValidate(payload): JSONValidate<UserMerge>,
+) -> Result<impl IntoResponse> {
+    if user_id.len() < 20 || user_id == payload.source_id {
+        return Err(AppError::from(ErrorResponse::create_error(
+            "Invalid user id",
+        )));
+    }
+
+    let row =
+        merge_users(&mut conn, &user_id, &payload.source_id, &actor_id).await?;
+    info!("User {} merged into {}", payload.source_id, user_id);
+
+    let user = User::from_row(&row)?;
+
+    Ok(([(ETAG, version_etag(row.get(10)))], Json(user)).into_response())
 }